@@ -0,0 +1,58 @@
+use rust_net::Response;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+use std::time::Duration;
+
+//future completed by another thread after a delay, like a database client would
+#[derive(Default)]
+struct State {
+    done: bool,
+    waker: Option<Waker>
+}
+struct Delay(Arc<Mutex<State>>);
+
+fn delay(duration: Duration) -> Delay {
+    let state = Arc::new(Mutex::new(State::default()));
+    let thread_state = state.clone();
+    thread::spawn(move || {
+        thread::sleep(duration);
+        let mut state = thread_state.lock().unwrap();
+        state.done = true;
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    });
+    Delay(state)
+}
+
+impl Future for Delay {
+    type Output = ();
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let mut state = self.0.lock().unwrap();
+        if state.done {
+            Poll::Ready(())
+        } else {
+            state.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+fn main() {
+    let mut server = rust_net::Server::new(Default::default(), ());
+    //other connections are served while this route waits
+    server.add_async_get_route("slow", |_, _| Box::pin(async {
+        delay(Duration::from_secs(1)).await;
+        Response::ok().body("Slow").into()
+    }));
+    server.add_get_route("fast", |_, socket, _| {
+        socket.send_200(b"Fast");
+    });
+    println!("Server running ...");
+    if let Err(e) = server.run() {
+        println!("Server error: {}", e);
+    }
+}
@@ -1,18 +1,114 @@
+use std::io::Read;
+
+#[derive(Clone)]
 struct Context {
     a: String
 }
 
+//handler keeping its own state, each worker counts its own requests
+#[derive(Clone, Default)]
+struct Counter {
+    count: usize
+}
+impl rust_net::Handler<Context> for Counter {
+    fn call(&mut self, _: &mut Context, socket: &mut rust_net::Socket, _: rust_net::Request) {
+        self.count += 1;
+        socket.send_200(self.count.to_string().as_bytes());
+    }
+}
+
 fn main() {
     let context = Context {
         a: "test".to_string()
     };
-    let mut server = rust_net::Server::new(Default::default(), context);
+    let settings = rust_net::Settings {
+        workers: 2,
+        static_files: Some(rust_net::StaticFilesSettings {
+            not_found_page: Some("404.html"),
+            cache_control: vec![
+                rust_net::CacheControlRule::glob("index.html", "no-cache"),
+                rust_net::CacheControlRule::extension("css", "max-age=31536000, immutable")
+            ],
+            ..Default::default()
+        }),
+        cors: Some(rust_net::CorsSettings {
+            allowed_origins: vec!["http://localhost:8080"],
+            allowed_headers: vec!["Authorization", "Content-Type"],
+            max_age: Some(std::time::Duration::from_secs(3600)),
+            ..Default::default()
+        }),
+        access_log: Some(rust_net::AccessLogSettings {
+            format: rust_net::AccessLogFormat::Combined,
+            sink: rust_net::AccessLogSink::Stdout
+        }),
+        ..Default::default()
+    };
+    let mut server = rust_net::Server::new(settings, context);
     server.add_get_route("test", |_, socket, _| {
         socket.send_200(b"Test");
     });
     server.add_post_route("test", |context, socket, _| {
         socket.send_200(context.a.as_bytes());
     });
+    server.add_get_route("users/:id", |_, socket, request| {
+        socket.send_200(request.param("id").unwrap_or_default());
+    });
+    server.add_get_route("files/*path", |_, socket, request| {
+        socket.send_200(request.param("path").unwrap_or_default());
+    });
+    server.add_get_route("search", |_, socket, request| {
+        let tags: Vec<&[u8]> = request.query.get_all("tag").collect();
+        socket.send_200(&tags.join(&b',')[..]);
+    });
+    server.add_post_route("echo", |_, socket, request| {
+        socket.send_200(&request.body);
+    });
+    server.add_route(rust_net::Method::Delete, "test", |_, socket, _| {
+        socket.send_200(b"Deleted");
+    });
+    let greeting = "Hello".to_string();
+    server.add_get_route("hello/:name", move |_, socket, request| {
+        let name = String::from_utf8_lossy(request.param("name").unwrap_or_default());
+        socket.send_200(format!("{} {}", greeting, name).as_bytes());
+    });
+    server.add_handler(rust_net::Method::Get, "count", Counter::default());
+    server.add_post_route("users", |_, socket, request| {
+        socket.send(rust_net::Response::new(201)
+            .header("Content-Type", "application/json")
+            .header("Location", "/users/1")
+            .body(request.body));
+    });
+    server.add_get_route("readme", |_, socket, _| match rust_net::Response::file("README.md") {
+        Ok(response) => socket.send(response),
+        Err(e) => socket.send_500(e)
+    });
+    //body of unknown length, sent chunked
+    server.add_get_route("stream", |_, socket, _| {
+        socket.send(rust_net::Response::ok().stream(std::io::repeat(b'a').take(100_000), None));
+    });
+    //requests to "admin" routes need a token
+    server.add_before(|_, request| {
+        if request.path().starts_with(b"admin") && request.header("Authorization") != Some(b"Bearer secret") {
+            return Some(rust_net::Response::new(401).header("WWW-Authenticate", "Bearer"))
+        }
+        None
+    });
+    server.add_after(|_, _, response| {
+        response.headers.set("Server", "rust-net");
+    });
+    server.add_get_route("admin", |_, socket, _| {
+        socket.send_200(b"Admin");
+    });
+    server.add_get_route("panic", |_, _, _| {
+        panic!("Route failed");
+    });
+    server.add_error_handler(500, |_, _, response| {
+        response.header("Content-Type", "application/json").body("{\"error\":\"Internal error\"}")
+    });
+    #[cfg(feature = "signals")]
+    server.shutdown_handle().shutdown_on_signals().unwrap();
     println!("Server running ...");
-    server.run();
+    if let Err(e) = server.run() {
+        println!("Server error: {}", e);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,211 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime};
+use super::{AccessLogFormat, AccessLogSettings, AccessLogSink, Request};
+use super::util::{DateTime, MONTHS};
+
+enum Sink {
+    Stdout,
+    File(Mutex<File>),
+    Callback(Arc<dyn Fn(&str) + Send + Sync>)
+}
+
+struct Shared {
+    format: AccessLogFormat,
+    sink: Sink
+}
+
+//writes a line for every response, shared by the workers
+#[derive(Clone)]
+pub(crate) struct AccessLog(Arc<Shared>);
+
+//fields of a request kept until its response is written
+pub(crate) struct Entry {
+    time: SystemTime,
+    started: Instant,
+    method: Vec<u8>,
+    target: Vec<u8>,
+    version: &'static [u8],
+    referer: Option<Vec<u8>>,
+    user_agent: Option<Vec<u8>>
+}
+
+impl Entry {
+    pub fn new(request: &Request) -> Self {
+        Self {
+            time: SystemTime::now(),
+            started: Instant::now(),
+            method: Vec::from(request.method.to_bytes()),
+            target: request.target.clone(),
+            version: request.version.to_bytes(),
+            referer: request.header("Referer").map(Vec::from),
+            user_agent: request.header("User-Agent").map(Vec::from)
+        }
+    }
+}
+
+impl AccessLog {
+    pub fn new(settings: AccessLogSettings) -> io::Result<Self> {
+        let sink = match settings.sink {
+            AccessLogSink::Stdout => Sink::Stdout,
+            AccessLogSink::File(path) => Sink::File(Mutex::new(OpenOptions::new().create(true).append(true).open(path)?)),
+            AccessLogSink::Callback(callback) => Sink::Callback(callback)
+        };
+        Ok(Self(Arc::new(Shared {
+            format: settings.format,
+            sink
+        })))
+    }
+    //log a response, without entry for requests that couldn't be parsed.
+    //`bytes` is the length of the sent body, None if it is streamed without a known length
+    pub fn write(&self, address: SocketAddr, entry: Option<Entry>, status: u16, bytes: Option<u64>) {
+        let line = match self.0.format {
+            AccessLogFormat::Common => common(address, entry.as_ref(), status, bytes),
+            AccessLogFormat::Combined => {
+                let (referer, user_agent) = match &entry {
+                    Some(entry) => (entry.referer.as_deref(), entry.user_agent.as_deref()),
+                    None => (None, None)
+                };
+                format!("{} \"{}\" \"{}\"", common(address, entry.as_ref(), status, bytes),
+                    referer.map_or_else(|| "-".to_string(), escape), user_agent.map_or_else(|| "-".to_string(), escape))
+            },
+            AccessLogFormat::Json => json(address, entry.as_ref(), status, bytes)
+        };
+        //a failing sink doesn't stop the server
+        let _ = match &self.0.sink {
+            Sink::Stdout => writeln!(io::stdout().lock(), "{}", line),
+            Sink::File(file) => match file.lock() {
+                Ok(mut file) => writeln!(file, "{}", line),
+                Err(_) => Ok(())
+            },
+            Sink::Callback(callback) => {
+                callback(&line);
+                Ok(())
+            }
+        };
+    }
+}
+
+//host ident user [10/Oct/2000:13:55:36 +0000] "GET /index.html HTTP/1.1" 200 2326
+fn common(address: SocketAddr, entry: Option<&Entry>, status: u16, bytes: Option<u64>) -> String {
+    let time = DateTime::from(entry.map_or_else(SystemTime::now, |entry| entry.time));
+    let request = match entry {
+        Some(entry) => format!("{} {} {}", escape(&entry.method), escape(&entry.target), escape(entry.version)),
+        None => "-".to_string()
+    };
+    format!("{} - - [{:02}/{}/{}:{:02}:{:02}:{:02} +0000] \"{}\" {} {}",
+        address.ip(), time.day, MONTHS[time.month as usize - 1], time.year, time.hour, time.minute, time.second,
+        request, status, bytes.filter(|bytes| *bytes > 0).map_or_else(|| "-".to_string(), |bytes| bytes.to_string()))
+}
+
+fn json(address: SocketAddr, entry: Option<&Entry>, status: u16, bytes: Option<u64>) -> String {
+    let time = DateTime::from(entry.map_or_else(SystemTime::now, |entry| entry.time));
+    let string = |v: Option<&[u8]>| v.map_or_else(|| "null".to_string(), json_string);
+    format!("{{\"time\":\"{}-{:02}-{:02}T{:02}:{:02}:{:02}Z\",\"remote_addr\":\"{}\",\"method\":{},\"path\":{},\"version\":{},\"status\":{},\"bytes\":{},\"latency_ms\":{:.3},\"referer\":{},\"user_agent\":{}}}",
+        time.year, time.month, time.day, time.hour, time.minute, time.second,
+        address.ip(),
+        string(entry.map(|entry| &entry.method[..])),
+        string(entry.map(|entry| &entry.target[..])),
+        string(entry.map(|entry| entry.version)),
+        status,
+        bytes.map_or_else(|| "null".to_string(), |bytes| bytes.to_string()),
+        entry.map_or(0.0, |entry| entry.started.elapsed().as_secs_f64() * 1000.0),
+        string(entry.and_then(|entry| entry.referer.as_deref())),
+        string(entry.and_then(|entry| entry.user_agent.as_deref())))
+}
+
+//escape quotes, backslashes and bytes that aren't printable ascii the way apache does
+fn escape(v: &[u8]) -> String {
+    let mut res = String::with_capacity(v.len());
+    for c in v {
+        match c {
+            b'"' => res.push_str("\\\""),
+            b'\\' => res.push_str("\\\\"),
+            b' '..=b'~' => res.push(*c as char),
+            _ => res.push_str(&format!("\\x{:02x}", c))
+        }
+    }
+    res
+}
+
+fn json_string(v: &[u8]) -> String {
+    let mut res = String::with_capacity(v.len() + 2);
+    res.push('"');
+    for c in String::from_utf8_lossy(v).chars() {
+        match c {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            c if (c as u32) < 0x20 => res.push_str(&format!("\\u{:04x}", c as u32)),
+            c => res.push(c)
+        }
+    }
+    res.push('"');
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn entry() -> Entry {
+        Entry {
+            time: UNIX_EPOCH + Duration::from_secs(971_186_136),
+            started: Instant::now(),
+            method: b"GET".to_vec(),
+            target: b"/a b\"\x01".to_vec(),
+            version: b"HTTP/1.1",
+            referer: Some(b"http://example.com/".to_vec()),
+            user_agent: None
+        }
+    }
+
+    fn address() -> SocketAddr {
+        "127.0.0.1:8080".parse().unwrap()
+    }
+
+    #[test]
+    fn common_format() {
+        assert_eq!(common(address(), Some(&entry()), 200, Some(2326)),
+            "127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \"GET /a b\\\"\\x01 HTTP/1.1\" 200 2326");
+        //empty and streamed bodies have no size
+        assert!(common(address(), Some(&entry()), 304, Some(0)).ends_with("\" 304 -"));
+        assert!(common(address(), Some(&entry()), 200, None).ends_with("\" 200 -"));
+        //requests that couldn't be parsed
+        assert!(common(address(), None, 400, Some(0)).ends_with("] \"-\" 400 -"));
+    }
+
+    #[test]
+    fn json_format() {
+        let line = json(address(), Some(&entry()), 404, None);
+        assert!(line.starts_with("{\"time\":\"2000-10-10T13:55:36Z\",\"remote_addr\":\"127.0.0.1\",\"method\":\"GET\",\
+            \"path\":\"/a b\\\"\\u0001\",\"version\":\"HTTP/1.1\",\"status\":404,\"bytes\":null,\"latency_ms\":"));
+        assert!(line.ends_with(",\"referer\":\"http://example.com/\",\"user_agent\":null}"));
+        let line = json(address(), None, 400, Some(0));
+        assert!(line.contains("\"method\":null,\"path\":null,\"version\":null,\"status\":400,\"bytes\":0,\"latency_ms\":0.000,"));
+    }
+
+    #[test]
+    fn combined_format_to_a_callback() {
+        let lines = Arc::new(Mutex::new(vec![]));
+        let sink = lines.clone();
+        let access_log = AccessLog::new(AccessLogSettings {
+            format: AccessLogFormat::Combined,
+            sink: AccessLogSink::Callback(Arc::new(move |line| sink.lock().unwrap().push(line.to_string())))
+        }).unwrap();
+        access_log.write(address(), Some(entry()), 200, Some(5));
+        let lines = lines.lock().unwrap();
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].ends_with("\" 200 5 \"http://example.com/\" \"-\""));
+    }
+
+    #[test]
+    fn escapes_like_apache() {
+        assert_eq!(escape(b"plain text"), "plain text");
+        assert_eq!(escape(b"a\"b\\c"), "a\\\"b\\\\c");
+        assert_eq!(escape(b"\n\x7f\xc3\xa9"), "\\x0a\\x7f\\xc3\\xa9");
+        assert_eq!(json_string("é\"\\\t".as_bytes()), "\"é\\\"\\\\\\u0009\"");
+    }
+}
@@ -0,0 +1,143 @@
+use super::{CorsSettings, Method, Middleware, Request, Response};
+
+//answers preflight requests and adds the CORS headers to the responses of allowed origins,
+//added before the other middlewares so it also decorates their responses
+#[derive(Clone)]
+pub(crate) struct Cors(pub CorsSettings);
+
+impl Cors {
+    fn is_allowed(&self, origin: &[u8]) -> bool {
+        self.0.allowed_origins.iter().any(|allowed| *allowed == "*" || allowed.as_bytes().eq_ignore_ascii_case(origin))
+    }
+    //credentials can't be used with the "*" origin, so the origin is sent back instead
+    fn allow_origin(&self, origin: &[u8], response: &mut Response) {
+        if self.0.allowed_origins.contains(&"*") && !self.0.allow_credentials {
+            response.headers.set("Access-Control-Allow-Origin", "*");
+        } else {
+            response.headers.set("Access-Control-Allow-Origin", origin);
+            response.headers.append("Vary", "Origin");
+        }
+        if self.0.allow_credentials {
+            response.headers.set("Access-Control-Allow-Credentials", "true");
+        }
+    }
+}
+
+impl<T> Middleware<T> for Cors {
+    fn before(&mut self, _: &mut T, request: &mut Request) -> Option<Response> {
+        if request.method != Method::Options || !request.headers.contains("Access-Control-Request-Method") {
+            return None
+        }
+        let origin = request.header("Origin")?;
+        if !self.is_allowed(origin) {
+            return Some(Response::new(403))
+        }
+        let mut response = Response::new(204);
+        let methods: Vec<String> = self.0.allowed_methods.iter().map(|method| method.to_string()).collect();
+        response.headers.set("Access-Control-Allow-Methods", methods.join(", "));
+        //"*" allows the headers the client asks for
+        if self.0.allowed_headers.contains(&"*") {
+            if let Some(headers) = request.header("Access-Control-Request-Headers") {
+                response.headers.set("Access-Control-Allow-Headers", headers);
+            }
+            response.headers.append("Vary", "Access-Control-Request-Headers");
+        } else if !self.0.allowed_headers.is_empty() {
+            response.headers.set("Access-Control-Allow-Headers", self.0.allowed_headers.join(", "));
+        }
+        if let Some(max_age) = self.0.max_age {
+            response.headers.set("Access-Control-Max-Age", max_age.as_secs().to_string());
+        }
+        Some(response)
+    }
+    fn after(&mut self, _: &mut T, request: &Request, response: &mut Response) {
+        if let Some(origin) = request.header("Origin") {
+            if self.is_allowed(origin) {
+                self.allow_origin(origin, response);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn request(method: &str, headers: &str) -> Request {
+        let data = format!("{} /users HTTP/1.1\r\n{}\r\n", method, headers);
+        Request::parse(data.as_bytes(), 8192, 0).unwrap().unwrap().0
+    }
+
+    fn preflight(cors: &mut Cors, headers: &str) -> Option<Response> {
+        let mut request = request("OPTIONS", &format!("Access-Control-Request-Method: PUT\r\n{}", headers));
+        Middleware::<()>::before(cors, &mut (), &mut request)
+    }
+
+    fn after(cors: &mut Cors, headers: &str) -> Response {
+        let mut response = Response::ok();
+        Middleware::<()>::after(cors, &mut (), &request("GET", headers), &mut response);
+        response
+    }
+
+    fn header<'a>(response: &'a Response, name: &str) -> Option<&'a str> {
+        response.headers.get(name).map(|v| std::str::from_utf8(v).unwrap())
+    }
+
+    fn settings(origins: Vec<&'static str>, allow_credentials: bool) -> CorsSettings {
+        CorsSettings {
+            allowed_origins: origins,
+            allow_credentials,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn rejects_other_origins() {
+        let mut cors = Cors(settings(vec!["https://a.com"], false));
+        assert_eq!(preflight(&mut cors, "Origin: https://b.com\r\n").unwrap().status, 403);
+        let response = after(&mut cors, "Origin: https://b.com\r\n");
+        assert_eq!(header(&response, "Access-Control-Allow-Origin"), None);
+        let response = after(&mut cors, "Origin: HTTPS://A.COM\r\n");
+        assert_eq!(header(&response, "Access-Control-Allow-Origin"), Some("HTTPS://A.COM"));
+        assert_eq!(header(&response, "Vary"), Some("Origin"));
+        //not a preflight
+        assert!(preflight(&mut cors, "").is_none());
+        let mut options = request("OPTIONS", "Origin: https://b.com\r\n");
+        assert!(Middleware::<()>::before(&mut cors, &mut (), &mut options).is_none());
+    }
+
+    #[test]
+    fn wildcard_origin_with_credentials_echoes_the_origin() {
+        let mut cors = Cors(settings(vec!["*"], false));
+        let response = after(&mut cors, "Origin: https://b.com\r\n");
+        assert_eq!(header(&response, "Access-Control-Allow-Origin"), Some("*"));
+        assert_eq!(header(&response, "Vary"), None);
+
+        let mut cors = Cors(settings(vec!["*"], true));
+        let response = after(&mut cors, "Origin: https://b.com\r\n");
+        assert_eq!(header(&response, "Access-Control-Allow-Origin"), Some("https://b.com"));
+        assert_eq!(header(&response, "Access-Control-Allow-Credentials"), Some("true"));
+        assert_eq!(header(&response, "Vary"), Some("Origin"));
+    }
+
+    #[test]
+    fn preflight_headers() {
+        let mut cors = Cors(CorsSettings {
+            allowed_methods: vec![Method::Get, Method::Put],
+            allowed_headers: vec!["*"],
+            max_age: Some(Duration::from_secs(600)),
+            ..Default::default()
+        });
+        let response = preflight(&mut cors, "Origin: https://b.com\r\nAccess-Control-Request-Headers: X-Token, Content-Type\r\n").unwrap();
+        assert_eq!(response.status, 204);
+        assert_eq!(header(&response, "Access-Control-Allow-Methods"), Some("GET, PUT"));
+        assert_eq!(header(&response, "Access-Control-Allow-Headers"), Some("X-Token, Content-Type"));
+        assert_eq!(header(&response, "Vary"), Some("Access-Control-Request-Headers"));
+        assert_eq!(header(&response, "Access-Control-Max-Age"), Some("600"));
+
+        let mut cors = Cors(CorsSettings {allowed_headers: vec!["X-Token", "Content-Type"], ..Default::default()});
+        let response = preflight(&mut cors, "Origin: https://b.com\r\nAccess-Control-Request-Headers: X-Other\r\n").unwrap();
+        assert_eq!(header(&response, "Access-Control-Allow-Headers"), Some("X-Token, Content-Type"));
+        assert_eq!(header(&response, "Access-Control-Max-Age"), None);
+    }
+}
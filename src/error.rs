@@ -0,0 +1,31 @@
+use std::{fmt, io};
+use std::net::SocketAddr;
+
+#[derive(Debug)]
+pub enum Error {
+    //creating or waiting on the mio poll failed
+    Poll(io::Error),
+    //the listener couldn't bind to the address
+    Bind(SocketAddr, io::Error),
+    //the listener couldn't be registered in the poll
+    Register(io::Error),
+    //the access log file couldn't be opened
+    AccessLog(io::Error)
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Poll(e) => write!(f, "Poll error: {}", e),
+            Self::Bind(address, e) => write!(f, "Couldn't bind to {}: {}", address, e),
+            Self::Register(e) => write!(f, "Couldn't register the listener: {}", e),
+            Self::AccessLog(e) => write!(f, "Couldn't open the access log: {}", e)
+        }
+    }
+}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Poll(e) | Self::Bind(_, e) | Self::Register(e) | Self::AccessLog(e) => Some(e)
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+use super::{Socket, Response, ShutdownHandle};
+
+//what an async route answers once its future completes
+pub struct Reply(Box<dyn FnOnce(&mut Socket)>);
+
+impl Reply {
+    pub fn new<F: FnOnce(&mut Socket) + 'static>(func: F) -> Self {
+        Self(Box::new(func))
+    }
+    pub(crate) fn send(self, socket: &mut Socket) {
+        (self.0)(socket)
+    }
+}
+
+impl From<Response> for Reply {
+    fn from(response: Response) -> Self {
+        Self::new(|socket| socket.send(response))
+    }
+}
+
+//the context can only be used before the future is created, the future runs on the worker's event loop
+pub type RouteFuture = Pin<Box<dyn Future<Output = Reply>>>;
+
+struct Task {
+    future: RouteFuture,
+    //socket waiting for the reply
+    token: usize
+}
+
+struct TaskWaker {
+    id: usize,
+    ready: Arc<Mutex<Vec<usize>>>,
+    //wakes the worker's poll, futures can be woken from other threads
+    worker: ShutdownHandle
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.ready.lock().unwrap().push(self.id);
+        self.worker.wake();
+    }
+}
+
+//runs the futures of async routes on the worker's thread, woken up through the worker's mio waker
+pub(crate) struct Executor {
+    tasks: Vec<Option<Task>>,
+    free: Vec<usize>,
+    ready: Arc<Mutex<Vec<usize>>>,
+    worker: ShutdownHandle
+}
+
+impl Executor {
+    pub fn new(worker: ShutdownHandle) -> Self {
+        Self {
+            tasks: vec![],
+            free: vec![],
+            ready: Arc::default(),
+            worker
+        }
+    }
+    //the future is polled the next time `run` is called, after waking the worker
+    pub fn spawn(&mut self, token: usize, future: RouteFuture) {
+        let task = Some(Task {future, token});
+        let id = match self.free.pop() {
+            Some(id) => {
+                self.tasks[id] = task;
+                id
+            },
+            None => {
+                self.tasks.push(task);
+                self.tasks.len() - 1
+            }
+        };
+        self.ready.lock().unwrap().push(id);
+        self.worker.wake();
+    }
+    //poll the woken futures, returns the replies of the completed ones with their socket token,
+    //None for the ones that panicked.
+    //futures woken while polling are polled on the next call, after the worker's poll wakes up again
+    pub fn run(&mut self) -> Vec<(usize, Option<Reply>)> {
+        let mut replies = vec![];
+        let ready = std::mem::take(&mut *self.ready.lock().unwrap());
+        for id in ready {
+            //woken after completing
+            let task = match self.tasks.get_mut(id) {
+                Some(Some(task)) => task,
+                _ => continue
+            };
+            let waker = Waker::from(Arc::new(TaskWaker {
+                id,
+                ready: self.ready.clone(),
+                worker: self.worker.clone()
+            }));
+            let reply = match panic::catch_unwind(AssertUnwindSafe(|| task.future.as_mut().poll(&mut Context::from_waker(&waker)))) {
+                Ok(Poll::Ready(reply)) => Some(reply),
+                Ok(Poll::Pending) => continue,
+                Err(_) => None
+            };
+            replies.push((task.token, reply));
+            self.tasks[id] = None;
+            self.free.push(id);
+        }
+        replies
+    }
+}
@@ -0,0 +1,64 @@
+use super::{Request, Response, RouteFuture, Socket};
+
+//answers the requests of a route, implemented by closures and by structs that keep their own state.
+//every worker gets its own clone of the handler
+pub trait Handler<T>: HandlerClone<T> + Send + 'static {
+    fn call(&mut self, context: &mut T, socket: &mut Socket, request: Request);
+}
+
+//answers the requests of a route with a future run by the worker's event loop
+pub trait AsyncHandler<T>: AsyncHandlerClone<T> + Send + 'static {
+    fn call(&mut self, context: &mut T, request: Request) -> RouteFuture;
+}
+
+impl<T, F> Handler<T> for F where F: FnMut(&mut T, &mut Socket, Request) + Clone + Send + 'static {
+    fn call(&mut self, context: &mut T, socket: &mut Socket, request: Request) {
+        self(context, socket, request)
+    }
+}
+
+impl<T, F> AsyncHandler<T> for F where F: FnMut(&mut T, Request) -> RouteFuture + Clone + Send + 'static {
+    fn call(&mut self, context: &mut T, request: Request) -> RouteFuture {
+        self(context, request)
+    }
+}
+
+//implemented for every handler that is Clone
+pub trait HandlerClone<T> {
+    fn clone_box(&self) -> Box<dyn Handler<T>>;
+}
+impl<T, H: Handler<T> + Clone> HandlerClone<T> for H {
+    fn clone_box(&self) -> Box<dyn Handler<T>> {
+        Box::new(self.clone())
+    }
+}
+
+pub trait AsyncHandlerClone<T> {
+    fn clone_box(&self) -> Box<dyn AsyncHandler<T>>;
+}
+impl<T, H: AsyncHandler<T> + Clone> AsyncHandlerClone<T> for H {
+    fn clone_box(&self) -> Box<dyn AsyncHandler<T>> {
+        Box::new(self.clone())
+    }
+}
+
+//builds the response of an error answered by the server itself, from the default response.
+//the request is None for requests that couldn't be read and for routes that panicked
+pub trait ErrorHandler<T>: ErrorHandlerClone<T> + Send + 'static {
+    fn call(&mut self, context: &mut T, request: Option<&Request>, response: Response) -> Response;
+}
+
+impl<T, F> ErrorHandler<T> for F where F: FnMut(&mut T, Option<&Request>, Response) -> Response + Clone + Send + 'static {
+    fn call(&mut self, context: &mut T, request: Option<&Request>, response: Response) -> Response {
+        self(context, request, response)
+    }
+}
+
+pub trait ErrorHandlerClone<T> {
+    fn clone_box(&self) -> Box<dyn ErrorHandler<T>>;
+}
+impl<T, H: ErrorHandler<T> + Clone> ErrorHandlerClone<T> for H {
+    fn clone_box(&self) -> Box<dyn ErrorHandler<T>> {
+        Box::new(self.clone())
+    }
+}
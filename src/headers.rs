@@ -0,0 +1,78 @@
+//header fields in the order they were received, names are compared case-insensitively
+#[derive(Default, Debug, Clone)]
+pub struct Headers(Vec<(Vec<u8>, Vec<u8>)>);
+
+impl Headers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    //first value of the header
+    pub fn get(&self, name: &str) -> Option<&[u8]> {
+        self.0.iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name.as_bytes()))
+            .map(|(_, v)| v.as_slice())
+    }
+    //every value of a header sent on multiple lines, e.g. "Cookie" or "Accept"
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a [u8]> {
+        self.0.iter()
+            .filter(move |(k, _)| k.eq_ignore_ascii_case(name.as_bytes()))
+            .map(|(_, v)| v.as_slice())
+    }
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+    //add a value, keeping the existing ones
+    pub fn append<K: AsRef<[u8]>, V: AsRef<[u8]>>(&mut self, name: K, value: V) {
+        self.0.push((Vec::from(name.as_ref()), Vec::from(value.as_ref())));
+    }
+    //replace every value of the header
+    pub fn set<K: AsRef<[u8]>, V: AsRef<[u8]>>(&mut self, name: K, value: V) {
+        self.remove(&String::from_utf8_lossy(name.as_ref()));
+        self.append(name, value);
+    }
+    pub fn remove(&mut self, name: &str) {
+        self.0.retain(|(k, _)| !k.eq_ignore_ascii_case(name.as_bytes()));
+    }
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], &[u8])> {
+        self.0.iter().map(|(k, v)| (k.as_slice(), v.as_slice()))
+    }
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers() -> Headers {
+        let mut headers = Headers::new();
+        headers.append("Content-Type", "text/html");
+        headers.append("accept", "text/html");
+        headers.append("ACCEPT", "*/*");
+        headers
+    }
+
+    #[test]
+    fn names_are_case_insensitive() {
+        let headers = headers();
+        assert_eq!(headers.get("content-type"), Some(&b"text/html"[..]));
+        assert_eq!(headers.get("Accept"), Some(&b"text/html"[..]));
+        assert_eq!(headers.get_all("Accept").collect::<Vec<_>>(), vec![&b"text/html"[..], &b"*/*"[..]]);
+        assert!(headers.contains("CONTENT-TYPE") && !headers.contains("Content-Length"));
+        assert_eq!(headers.get_all("Content-Length").count(), 0);
+    }
+
+    #[test]
+    fn set_replaces_every_value() {
+        let mut headers = headers();
+        headers.set("Accept", "application/json");
+        assert_eq!(headers.get_all("accept").collect::<Vec<_>>(), vec![&b"application/json"[..]]);
+        headers.remove("content-type");
+        assert_eq!(headers.iter().map(|(k, _)| k).collect::<Vec<_>>(), vec![&b"Accept"[..]]);
+        assert_eq!(headers.len(), 1);
+    }
+}
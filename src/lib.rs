@@ -1,163 +1,309 @@
-use mio::{Events, Interest, Poll};
+use mio::{Events, Interest, Poll, Waker};
 pub use mio::net::{TcpListener, TcpStream};
+use std::io::ErrorKind::Interrupted;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::panic::{self, AssertUnwindSafe};
 use std::collections::HashMap;
+use std::sync::{Arc, mpsc::{self, Sender}};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
 
 pub mod util;
 mod socket;
 #[macro_use]
+mod request_body;
 mod response;
 mod settings;
 mod static_files;
+mod request;
+mod router;
+mod query;
+mod headers;
+mod error;
+mod shutdown;
+mod worker;
+mod executor;
+mod handler;
+mod middleware;
+mod access_log;
+mod cors;
+use worker::{Worker, accept, ACCEPT_RETRY_DELAY};
+pub use request_body::*;
 pub use response::*;
 pub use socket::*;
 pub use settings::*;
 pub use static_files::*;
+pub use request::*;
+pub use router::*;
+pub use query::*;
+pub use headers::*;
+pub use error::*;
+pub use shutdown::*;
+pub use executor::{Reply, RouteFuture};
+pub use handler::*;
+pub use middleware::{Middleware, MiddlewareClone};
+use middleware::{Middlewares, Before, After};
+use access_log::AccessLog;
+use cors::Cors;
 
-pub type RouteFunction<T> = fn(&mut T, &mut Socket, Vec<u8>);
-struct Routes<T> {
-    get: HashMap<Vec<u8>, RouteFunction<T>>,
-    post: HashMap<Vec<u8>, RouteFunction<T>>
+pub(crate) enum Route<T> {
+    Sync(Box<dyn Handler<T>>),
+    Async(Box<dyn AsyncHandler<T>>)
+}
+impl<T> Clone for Route<T> {
+    fn clone(&self) -> Self {
+        match self {
+            Self::Sync(handler) => Self::Sync(handler.clone_box()),
+            Self::Async(handler) => Self::Async(handler.clone_box())
+        }
+    }
+}
+pub(crate) struct Routes<T> {
+    methods: HashMap<Method, Router<Route<T>>>,
+    //answers the requests no route matches
+    fallback: Option<Route<T>>,
+    //build the responses of the errors answered by the server, by status
+    errors: HashMap<u16, Box<dyn ErrorHandler<T>>>
+}
+impl<T> Default for Routes<T> {
+    fn default() -> Self {
+        Self {
+            methods: HashMap::new(),
+            fallback: None,
+            errors: HashMap::new()
+        }
+    }
+}
+impl<T> Clone for Routes<T> {
+    fn clone(&self) -> Self {
+        Self {
+            methods: self.methods.clone(),
+            fallback: self.fallback.clone(),
+            errors: self.errors.iter().map(|(status, handler)| (*status, handler.clone_box())).collect()
+        }
+    }
+}
+impl<T> Routes<T> {
+    fn insert(&mut self, method: Method, path: &[u8], route: Route<T>) {
+        self.methods.entry(method).or_default().insert(path, route);
+    }
+    fn get_mut(&mut self, method: &Method, path: &[u8]) -> Option<(&mut Route<T>, Params)> {
+        self.methods.get_mut(method)?.find_mut(path)
+    }
+    fn contains(&self, method: &Method, path: &[u8]) -> bool {
+        match self.methods.get(method) {
+            Some(router) => router.find(path).is_some(),
+            None => false
+        }
+    }
+    //methods with a route for the path, for the "Allow" header
+    fn allowed_methods(&self, path: &[u8]) -> Vec<&Method> {
+        let mut methods: Vec<&Method> = self.methods.iter()
+            .filter(|(_, router)| router.find(path).is_some())
+            .map(|(method, _)| method)
+            .collect();
+        if methods.contains(&&Method::Get) && !methods.contains(&&Method::Head) {
+            methods.push(&Method::Head);
+        }
+        methods.sort_by_key(|method| method.to_string());
+        methods
+    }
+}
+impl<T: 'static> Routes<T> {
+    //a panicking error handler, even the one of the 500 of a panicking route, gets a plain 500
+    fn error(&mut self, context: &mut T, request: Option<&Request>, response: Response) -> Response {
+        match self.errors.get_mut(&response.status) {
+            Some(handler) => panic::catch_unwind(AssertUnwindSafe(|| handler.call(context, request, response)))
+                .unwrap_or_else(|_| Response::new(500)),
+            None => response
+        }
+    }
 }
 
 pub struct Server<T> {
     address: SocketAddr,
-    clients: Sockets,
+    workers: usize,
+    socket_settings: SocketSettings,
     static_files: Option<StaticFiles>,
+    access_log: Option<AccessLogSettings>,
     routes: Routes<T>,
+    middlewares: Middlewares<T>,
+    shutdown: ShutdownHandle,
+    shutdown_timeout: Duration,
     context: T
 }
 
 impl<T> Server<T> {
     pub fn new(settings: Settings, context: T) -> Self {
+        let mut middlewares = Middlewares::default();
+        if let Some(cors) = settings.cors {
+            middlewares.push(Box::new(Cors(cors)));
+        }
         Self {
             address: SocketAddr::new(
-                IpAddr::V4(Ipv4Addr::new(settings.address[0], settings.address[1], settings.address[2], settings.address[3])), 
+                IpAddr::V4(Ipv4Addr::new(settings.address[0], settings.address[1], settings.address[2], settings.address[3])),
                 settings.port
             ),
-            clients: Sockets::new(settings.socket),
-            static_files: match settings.static_files {
-                Some(static_files_settings) => 
-                    Some(StaticFiles::new(static_files_settings.root_path, static_files_settings.enable_cache)),
-                None => None
-            },
-            routes: Routes {
-                get: HashMap::new(),
-                post: HashMap::new()
-            },
+            workers: settings.workers,
+            socket_settings: settings.socket,
+            static_files: settings.static_files.map(StaticFiles::from),
+            access_log: settings.access_log,
+            routes: Routes::default(),
+            middlewares,
+            shutdown: ShutdownHandle::default(),
+            shutdown_timeout: settings.shutdown_timeout,
             context
         }
     }
-    pub fn add_get_route<S: AsRef<str>>(&mut self, path: S, func: RouteFunction<T>) {
-        self.routes.get.insert(Vec::from(path.as_ref().as_bytes()), func);
+    pub fn add_handler<S: AsRef<str>, H: Handler<T>>(&mut self, method: Method, path: S, handler: H) {
+        self.routes.insert(method, path.as_ref().as_bytes(), Route::Sync(Box::new(handler)));
+    }
+    pub fn add_route<S: AsRef<str>, F>(&mut self, method: Method, path: S, func: F)
+    where F: FnMut(&mut T, &mut Socket, Request) + Clone + Send + 'static {
+        self.add_handler(method, path, func);
+    }
+    pub fn add_get_route<S: AsRef<str>, F>(&mut self, path: S, func: F)
+    where F: FnMut(&mut T, &mut Socket, Request) + Clone + Send + 'static {
+        self.add_route(Method::Get, path, func);
     }
-    pub fn add_post_route<S: AsRef<str>>(&mut self, path: S, func: RouteFunction<T>) {
-        self.routes.post.insert(Vec::from(path.as_ref().as_bytes()), func);
+    pub fn add_post_route<S: AsRef<str>, F>(&mut self, path: S, func: F)
+    where F: FnMut(&mut T, &mut Socket, Request) + Clone + Send + 'static {
+        self.add_route(Method::Post, path, func);
     }
-    pub fn run(self) {
-        let mut poll = Poll::new().unwrap();
-        let mut listener = TcpListener::bind(self.address).unwrap();
-        poll.registry().register(&mut listener, LISTENER_EVENT_TOKEN, Interest::READABLE).unwrap();
+    pub fn add_put_route<S: AsRef<str>, F>(&mut self, path: S, func: F)
+    where F: FnMut(&mut T, &mut Socket, Request) + Clone + Send + 'static {
+        self.add_route(Method::Put, path, func);
+    }
+    pub fn add_delete_route<S: AsRef<str>, F>(&mut self, path: S, func: F)
+    where F: FnMut(&mut T, &mut Socket, Request) + Clone + Send + 'static {
+        self.add_route(Method::Delete, path, func);
+    }
+    pub fn add_patch_route<S: AsRef<str>, F>(&mut self, path: S, func: F)
+    where F: FnMut(&mut T, &mut Socket, Request) + Clone + Send + 'static {
+        self.add_route(Method::Patch, path, func);
+    }
+    //the returned future is run by the worker's event loop without blocking other connections
+    pub fn add_async_handler<S: AsRef<str>, H: AsyncHandler<T>>(&mut self, method: Method, path: S, handler: H) {
+        self.routes.insert(method, path.as_ref().as_bytes(), Route::Async(Box::new(handler)));
+    }
+    pub fn add_async_route<S: AsRef<str>, F>(&mut self, method: Method, path: S, func: F)
+    where F: FnMut(&mut T, Request) -> RouteFuture + Clone + Send + 'static {
+        self.add_async_handler(method, path, func);
+    }
+    pub fn add_async_get_route<S: AsRef<str>, F>(&mut self, path: S, func: F)
+    where F: FnMut(&mut T, Request) -> RouteFuture + Clone + Send + 'static {
+        self.add_async_route(Method::Get, path, func);
+    }
+    pub fn add_async_post_route<S: AsRef<str>, F>(&mut self, path: S, func: F)
+    where F: FnMut(&mut T, Request) -> RouteFuture + Clone + Send + 'static {
+        self.add_async_route(Method::Post, path, func);
+    }
+    //answers the requests no route or static file matches, instead of a 404 or a 405
+    pub fn set_fallback_route<F>(&mut self, func: F)
+    where F: FnMut(&mut T, &mut Socket, Request) + Clone + Send + 'static {
+        self.routes.fallback = Some(Route::Sync(Box::new(func)));
+    }
+    //builds the responses of an error status answered by the server: 404 and 405 for requests without a route,
+    //400 and 413 for requests that can't be read and 500 for routes that panic
+    pub fn add_error_handler<F>(&mut self, status: u16, func: F)
+    where F: FnMut(&mut T, Option<&Request>, Response) -> Response + Clone + Send + 'static {
+        self.routes.errors.insert(status, Box::new(func));
+    }
+    //middlewares run in the order they are added before the routes and in the reverse order after them
+    pub fn add_middleware<M: Middleware<T>>(&mut self, middleware: M) {
+        self.middlewares.push(Box::new(middleware));
+    }
+    //a returned response is sent instead of calling the route
+    pub fn add_before<F>(&mut self, func: F)
+    where F: FnMut(&mut T, &mut Request) -> Option<Response> + Clone + Send + 'static {
+        self.add_middleware(Before(func));
+    }
+    pub fn add_after<F>(&mut self, func: F)
+    where F: FnMut(&mut T, &Request, &mut Response) + Clone + Send + 'static {
+        self.add_middleware(After(func));
+    }
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+}
 
-        let mut clients = self.clients;
-        let static_files = self.static_files;
-        let mut routes = self.routes;
-        let mut context = self.context;
+impl<T: 'static> Server<T> {
+    //run on the current thread whatever the number of workers, the context doesn't have to be Clone or Send
+    pub fn run_single_worker(self) -> Result<(), Error> {
+        let listener = TcpListener::bind(self.address).map_err(|e| Error::Bind(self.address, e))?;
+        let access_log = self.access_log.map(AccessLog::new).transpose().map_err(Error::AccessLog)?;
+        Worker::new(
+            Some(listener), None, Sockets::new(self.socket_settings, access_log), Arc::new(self.static_files),
+            self.routes, self.middlewares, self.context, self.shutdown, self.shutdown_timeout
+        )?.run()
+    }
+}
 
-        let mut events = Events::with_capacity(1024);
-        loop {
-            poll.poll(&mut events, None).unwrap();
-            for event in events.iter() {
-                Self::new_event(&event, &poll, &mut listener, &mut clients, &static_files, &mut routes, &mut context);
-            }
+//connection sender, shutdown handle and thread of a worker
+type WorkerThread = (Sender<(TcpStream, SocketAddr)>, ShutdownHandle, JoinHandle<Result<(), Error>>);
+
+//each worker runs its own event loop with a clone of the routes and of the context,
+//state shared between workers has to live behind an Arc inside the context
+impl<T: Clone + Send + 'static> Server<T> {
+    pub fn run(self) -> Result<(), Error> {
+        if self.workers <= 1 {
+            return self.run_single_worker()
+        }
+        let listener = TcpListener::bind(self.address).map_err(|e| Error::Bind(self.address, e))?;
+        let static_files = Arc::new(self.static_files);
+        let access_log = self.access_log.map(AccessLog::new).transpose().map_err(Error::AccessLog)?;
+        let mut workers = Vec::with_capacity(self.workers);
+        for _ in 0..self.workers {
+            let (sender, receiver) = mpsc::channel();
+            let shutdown = ShutdownHandle::default();
+            let worker = Worker::new(
+                None, Some(receiver), Sockets::new(self.socket_settings.clone(), access_log.clone()), static_files.clone(),
+                self.routes.clone(), self.middlewares.clone(), self.context.clone(), shutdown.clone(), self.shutdown_timeout
+            )?;
+            workers.push((sender, shutdown, thread::spawn(move || worker.run())));
+        }
+        let res = Self::dispatch(listener, &self.shutdown, &workers);
+        for (_, shutdown, _) in &workers {
+            shutdown.shutdown();
         }
+        //the first error of the workers is returned, a panic is passed on
+        workers.into_iter().fold(res, |res, (_, _, thread)| {
+            let worker_res = thread.join().unwrap_or_else(|e| std::panic::resume_unwind(e));
+            res.and(worker_res)
+        })
     }
-    fn new_event(
-        event: &mio::event::Event,
-        poll: &mio::Poll,
-        listener: &mut TcpListener,
-        sockets: &mut Sockets,
-        static_files: &Option<StaticFiles>,
-        routes: &mut Routes<T>,
-        context: &mut T
-    ) {
-        match event.token().0 {
-            0 => {
-                match listener.accept() {
-                    Ok((socket, _)) => {
-                        sockets.insert(poll, socket);
-                        poll.registry().reregister(listener, LISTENER_EVENT_TOKEN, Interest::READABLE).unwrap();
-                    },
-                    Err(e) => panic!("{}",e)
+    //accept connections and hand them to the workers in turns until the server is shut down
+    fn dispatch(
+        mut listener: TcpListener,
+        shutdown: &ShutdownHandle,
+        workers: &[WorkerThread]
+    ) -> Result<(), Error> {
+        let mut poll = Poll::new().map_err(Error::Poll)?;
+        poll.registry().register(&mut listener, LISTENER_EVENT_TOKEN, Interest::READABLE).map_err(Error::Register)?;
+        shutdown.set_waker(Waker::new(poll.registry(), WAKER_EVENT_TOKEN).map_err(Error::Register)?);
+        let mut events = Events::with_capacity(1024);
+        let mut next = 0;
+        let mut accept_failed = false;
+        while !shutdown.is_shutdown() {
+            let timeout = if accept_failed {Some(ACCEPT_RETRY_DELAY)} else {None};
+            if let Err(e) = poll.poll(&mut events, timeout) {
+                if e.kind() == Interrupted {
+                    continue
                 }
-            },
-            token => {
-                if let Some(socket) = sockets.get_mut(token) {
-                    match socket.read_stream() {
-                        Some(data) => {
-                            if data.len() > 0 {
-                                match data[0] {
-                                    // If the request start with G that means it's a GET request
-                                    util::U8_G => {
-                                        let mut path = util::read_data_until_space(&data, 5);
-                                        if path.len() == 0 {
-                                            path = util::INDEX;
-                                        }
-                                        match static_files {
-                                            //if static files is enabled
-                                            Some(static_files) => match static_files.cache {
-                                                //if static files cache is enabled
-                                                Some(ref cache) => match cache.get(path) {
-                                                    Some(file) =>
-                                                        socket.send_file(file),
-                                                    //if file dont exists send program get route
-                                                    None => match routes.get.get_mut(path) {
-                                                        Some(func) => func(context, socket, data),
-                                                        //if route dont exists send 404 error
-                                                        None => socket.send_404()
-                                                    }
-                                                },
-                                                //if static files cache is disabled, read file
-                                                None => match read_file(static_files.root_path, path) {
-                                                    Some(file) => socket.send_file(&file),
-                                                    //if file dont exists send program get route
-                                                    None => match routes.get.get_mut(path) {
-                                                        Some(func) => func(context, socket, data),
-                                                        //if route dont exists send 404 error
-                                                        None => socket.send_404()
-                                                    }
-                                                }
-                                            },
-                                            //if static files is disabled, send route
-                                            None => match routes.get.get_mut(path) {
-                                                Some(func) => func(context, socket, data),
-                                                None => socket.send_404()
-                                            }
-                                        }
-                                    },
-                                    // If the request start with P that means it's a POST request
-                                    util::U8_P => {
-                                        match routes.post.get_mut(util::read_data_until_space(&data, 6)) {
-                                            Some(func) => func(context, socket, data),
-                                            None => socket.send_404()
-                                        }
-                                    },
-                                    // If the request start with O that means it's a OPTIONS request (treated like a post request)
-                                    util::U8_O => {
-                                        match routes.post.get_mut(util::read_data_until_space(&data, 9)) {
-                                            Some(func) => func(context, socket, data),
-                                            None => socket.send_404()
-                                        }
-                                    },
-                                    _ => {}
-                                }
-                            }
-                        },
-                        None => {}
+                return Err(Error::Poll(e))
+            }
+            if accept_failed || events.iter().any(|event| event.token() == LISTENER_EVENT_TOKEN) {
+                accept_failed = !accept(&listener, |stream, address| {
+                    let (sender, worker_shutdown, _) = &workers[next];
+                    next = (next + 1) % workers.len();
+                    //a worker that stopped takes the server down with it
+                    match sender.send((stream, address)) {
+                        Ok(_) => worker_shutdown.wake(),
+                        Err(_) => shutdown.shutdown()
                     }
-                }
-                sockets.remove(&poll, token);
+                });
             }
         }
+        Ok(())
     }
 }
@@ -0,0 +1,123 @@
+use super::{Request, Response};
+
+//hooks run around every request, including static files and requests without a route.
+//every worker gets its own clone of the middleware
+pub trait Middleware<T>: MiddlewareClone<T> + Send + 'static {
+    //called in the order the middlewares were added, before the route.
+    //a returned response is sent instead of calling the next middlewares and the route
+    fn before(&mut self, _context: &mut T, _request: &mut Request) -> Option<Response> {
+        None
+    }
+    //called in the reverse order, with the response that is about to be sent.
+    //the request is the one given to the route, without its body
+    fn after(&mut self, _context: &mut T, _request: &Request, _response: &mut Response) {}
+}
+
+//implemented for every middleware that is Clone
+pub trait MiddlewareClone<T> {
+    fn clone_box(&self) -> Box<dyn Middleware<T>>;
+}
+impl<T, M: Middleware<T> + Clone> MiddlewareClone<T> for M {
+    fn clone_box(&self) -> Box<dyn Middleware<T>> {
+        Box::new(self.clone())
+    }
+}
+
+//middleware of a closure added with `Server::add_before`
+#[derive(Clone)]
+pub(crate) struct Before<F>(pub F);
+impl<T, F> Middleware<T> for Before<F> where F: FnMut(&mut T, &mut Request) -> Option<Response> + Clone + Send + 'static {
+    fn before(&mut self, context: &mut T, request: &mut Request) -> Option<Response> {
+        (self.0)(context, request)
+    }
+}
+
+//middleware of a closure added with `Server::add_after`
+#[derive(Clone)]
+pub(crate) struct After<F>(pub F);
+impl<T, F> Middleware<T> for After<F> where F: FnMut(&mut T, &Request, &mut Response) + Clone + Send + 'static {
+    fn after(&mut self, context: &mut T, request: &Request, response: &mut Response) {
+        (self.0)(context, request, response)
+    }
+}
+
+pub(crate) struct Middlewares<T>(Vec<Box<dyn Middleware<T>>>);
+impl<T> Default for Middlewares<T> {
+    fn default() -> Self {
+        Self(vec![])
+    }
+}
+impl<T> Clone for Middlewares<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.iter().map(|middleware| middleware.clone_box()).collect())
+    }
+}
+impl<T> Middlewares<T> {
+    pub fn push(&mut self, middleware: Box<dyn Middleware<T>>) {
+        self.0.push(middleware);
+    }
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+impl<T: 'static> Middlewares<T> {
+    //returns the response of the middleware that short-circuited the request,
+    //with the number of middlewares that ran including it
+    pub fn before(&mut self, context: &mut T, request: &mut Request) -> Option<(usize, Response)> {
+        self.0.iter_mut().enumerate().find_map(|(i, middleware)| Some((i + 1, middleware.before(context, request)?)))
+    }
+    //runs the first `count` middlewares, the ones whose `before` ran
+    pub fn after(&mut self, context: &mut T, request: &Request, response: &mut Response, count: usize) {
+        for middleware in self.0[..count].iter_mut().rev() {
+            middleware.after(context, request, response);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone)]
+    struct Record(&'static str, bool);
+    impl Middleware<Vec<String>> for Record {
+        fn before(&mut self, context: &mut Vec<String>, _request: &mut Request) -> Option<Response> {
+            context.push(format!("before {}", self.0));
+            if self.1 {Some(Response::new(401))} else {None}
+        }
+        fn after(&mut self, context: &mut Vec<String>, _request: &Request, _response: &mut Response) {
+            context.push(format!("after {}", self.0));
+        }
+    }
+
+    fn run(middlewares: &mut Middlewares<Vec<String>>) -> Vec<String> {
+        let mut context = vec![];
+        let mut request = Request::parse(b"GET / HTTP/1.1\r\n\r\n", 1024, 0).unwrap().unwrap().0;
+        let (count, mut response) = match middlewares.before(&mut context, &mut request) {
+            Some(short_circuit) => short_circuit,
+            None => (middlewares.len(), Response::ok())
+        };
+        middlewares.after(&mut context, &request, &mut response, count);
+        context
+    }
+
+    #[test]
+    fn after_runs_in_reverse_order() {
+        let mut middlewares = Middlewares::default();
+        middlewares.push(Box::new(Record("a", false)));
+        middlewares.push(Box::new(Record("b", false)));
+        assert_eq!(run(&mut middlewares), ["before a", "before b", "after b", "after a"]);
+    }
+
+    #[test]
+    fn short_circuit_skips_the_next_middlewares() {
+        let mut middlewares = Middlewares::default();
+        middlewares.push(Box::new(Record("a", false)));
+        middlewares.push(Box::new(Record("b", true)));
+        middlewares.push(Box::new(Record("c", false)));
+        assert_eq!(run(&mut middlewares), ["before a", "before b", "after b", "after a"]);
+    }
+}
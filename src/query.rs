@@ -0,0 +1,82 @@
+use super::util::percent_decode;
+
+//decoded "key=value" pairs of the query string, repeated keys are kept in order
+#[derive(Default, Debug, Clone)]
+pub struct Query(Vec<(Vec<u8>, Vec<u8>)>);
+
+impl Query {
+    pub fn parse(v: &[u8]) -> Option<Self> {
+        let mut pairs = vec![];
+        for pair in v.split(|c| *c == b'&').filter(|pair| !pair.is_empty()) {
+            let (key, value) = match pair.iter().position(|c| *c == b'=') {
+                Some(i) => (&pair[..i], &pair[i + 1..]),
+                None => (pair, &[][..])
+            };
+            pairs.push((decode(key)?, decode(value)?));
+        }
+        Some(Self(pairs))
+    }
+    //first value of the key
+    pub fn get(&self, key: &str) -> Option<&[u8]> {
+        self.0.iter()
+            .find(|(k, _)| k == key.as_bytes())
+            .map(|(_, v)| v.as_slice())
+    }
+    //every value of a repeated key, e.g. "?tag=a&tag=b"
+    pub fn get_all<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a [u8]> {
+        self.0.iter()
+            .filter(move |(k, _)| k == key.as_bytes())
+            .map(|(_, v)| v.as_slice())
+    }
+    pub fn contains(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], &[u8])> {
+        self.0.iter().map(|(k, v)| (k.as_slice(), v.as_slice()))
+    }
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+//query strings use '+' for spaces
+fn decode(v: &[u8]) -> Option<Vec<u8>> {
+    let v: Vec<u8> = v.iter().map(|c| if *c == b'+' {b' '} else {*c}).collect();
+    percent_decode(&v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_pairs() {
+        let query = Query::parse(b"name=J%C3%BCrgen+M&empty=&flag&&a%3Db=c%26d").unwrap();
+        assert_eq!(query.get("name"), Some("Jürgen M".as_bytes()));
+        assert_eq!(query.get("empty"), Some(&b""[..]));
+        assert_eq!(query.get("flag"), Some(&b""[..]));
+        assert_eq!(query.get("a=b"), Some(&b"c&d"[..]));
+        assert_eq!(query.get("missing"), None);
+        assert!(query.contains("flag") && !query.contains("Name"));
+        assert_eq!(query.len(), 4);
+    }
+
+    #[test]
+    fn repeated_keys_keep_their_order() {
+        let query = Query::parse(b"tag=a&x=1&tag=b").unwrap();
+        assert_eq!(query.get("tag"), Some(&b"a"[..]));
+        assert_eq!(query.get_all("tag").collect::<Vec<_>>(), vec![&b"a"[..], &b"b"[..]]);
+        assert_eq!(query.iter().map(|(k, _)| k).collect::<Vec<_>>(), vec![&b"tag"[..], &b"x"[..], &b"tag"[..]]);
+    }
+
+    #[test]
+    fn empty_and_invalid_queries() {
+        assert!(Query::parse(b"").unwrap().is_empty());
+        assert!(Query::parse(b"&&").unwrap().is_empty());
+        assert!(Query::parse(b"a=%2").is_none());
+        assert!(Query::parse(b"a%zz=1").is_none());
+    }
+}
@@ -0,0 +1,433 @@
+use std::fmt;
+use super::{util, Headers, Params, Query};
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Method {
+    Get, Post, Put, Delete, Patch, Head, Options, Connect, Trace, Custom(Vec<u8>)
+}
+impl Method {
+    pub fn get(v: &[u8]) -> Option<Self> {
+        if v.is_empty() || !v.iter().all(|c| is_token_char(*c)) {
+            return None
+        }
+        Some(match v {
+            b"GET" => Self::Get,
+            b"POST" => Self::Post,
+            b"PUT" => Self::Put,
+            b"DELETE" => Self::Delete,
+            b"PATCH" => Self::Patch,
+            b"HEAD" => Self::Head,
+            b"OPTIONS" => Self::Options,
+            b"CONNECT" => Self::Connect,
+            b"TRACE" => Self::Trace,
+            _ => Self::Custom(Vec::from(v))
+        })
+    }
+    pub fn to_bytes(&self) -> &[u8] {
+        match self {
+            Self::Get => b"GET",
+            Self::Post => b"POST",
+            Self::Put => b"PUT",
+            Self::Delete => b"DELETE",
+            Self::Patch => b"PATCH",
+            Self::Head => b"HEAD",
+            Self::Options => b"OPTIONS",
+            Self::Connect => b"CONNECT",
+            Self::Trace => b"TRACE",
+            Self::Custom(v) => v
+        }
+    }
+}
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", String::from_utf8_lossy(self.to_bytes()))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Version {
+    Http10, Http11
+}
+impl Version {
+    pub fn get(v: &[u8]) -> Option<Self> {
+        match v {
+            b"HTTP/1.0" => Some(Self::Http10),
+            b"HTTP/1.1" => Some(Self::Http11),
+            _ => None
+        }
+    }
+    pub fn to_bytes(&self) -> &'static [u8] {
+        match self {
+            Self::Http10 => b"HTTP/1.0",
+            Self::Http11 => b"HTTP/1.1"
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ParseError {
+    RequestLine, Method, Target, Query, Version, Header, ContentLength, TransferEncoding, Framing, Chunk,
+    //the request line or the header fields are longer than the max header length, or the body than the max body length
+    RequestLineTooLong, HeadersTooLarge, BodyTooLarge
+}
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::RequestLine => "Malformed request line",
+            Self::Method => "Invalid method",
+            Self::Target => "Invalid request target",
+            Self::Query => "Malformed query string",
+            Self::Version => "Unsupported HTTP version",
+            Self::Header => "Malformed header",
+            Self::ContentLength => "Invalid Content-Length",
+            Self::TransferEncoding => "Unsupported Transfer-Encoding",
+            Self::Framing => "Both Content-Length and Transfer-Encoding",
+            Self::Chunk => "Malformed chunked body",
+            Self::RequestLineTooLong => "Request line too long",
+            Self::HeadersTooLarge => "Header fields too large",
+            Self::BodyTooLarge => "Request body too large"
+        })
+    }
+}
+
+pub struct Request {
+    pub method: Method,
+    //raw target, as sent by the client
+    pub target: Vec<u8>,
+    //percent-decoded path of the target, without the leading '/' and the query string
+    pub path: Vec<u8>,
+    //decoded query string of the target
+    pub query: Query,
+    pub version: Version,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+    //filled with the captures of the matched route
+    pub params: Params
+}
+
+impl Request {
+    //parse the first request of `data`, returns the request and the number of bytes it used
+    //or None if more data is needed
+    pub fn parse(data: &[u8], max_header_length: usize, max_body_length: usize) -> Result<Option<(Self, usize)>, ParseError> {
+        let incomplete = |e| if data.len() > max_header_length {
+            Err(e)
+        } else {
+            Ok(None)
+        };
+        let mut i = 0;
+        //some clients send empty lines before the request line
+        let request_line = loop {
+            match next_line(data, &mut i) {
+                Some([]) => continue,
+                Some(line) => break line,
+                None => return incomplete(ParseError::RequestLineTooLong)
+            }
+        };
+        if i > max_header_length {
+            return Err(ParseError::RequestLineTooLong)
+        }
+        let mut parts = request_line.split(|c| *c == b' ');
+        let (method, target, version) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(m), Some(t), Some(v), None) => (m, t, v),
+            _ => return Err(ParseError::RequestLine)
+        };
+        let method = Method::get(method).ok_or(ParseError::Method)?;
+        if !is_valid_target(&method, target) {
+            return Err(ParseError::Target)
+        }
+        let query = match target.iter().position(|c| *c == b'?') {
+            Some(i) => Query::parse(&target[i + 1..]).ok_or(ParseError::Query)?,
+            None => Query::default()
+        };
+        //static files and middlewares see the decoded path, routes decode it segment by segment
+        let path = util::percent_decode(raw_path(target)).ok_or(ParseError::Target)?;
+        let version = Version::get(version).ok_or(ParseError::Version)?;
+        let mut headers = Headers::new();
+        loop {
+            let line = match next_line(data, &mut i) {
+                Some(line) => line,
+                None => return incomplete(ParseError::HeadersTooLarge)
+            };
+            if line.is_empty() {
+                break
+            }
+            let (name, value) = parse_header(line)?;
+            headers.append(name, value);
+        }
+        if i > max_header_length {
+            return Err(ParseError::HeadersTooLarge)
+        }
+        let body = if headers.contains("Transfer-Encoding") {
+            //a proxy could frame the request by the other header, letting a request be smuggled in the body
+            if headers.contains("Content-Length") {
+                return Err(ParseError::Framing)
+            }
+            if !is_chunked(&headers) {
+                return Err(ParseError::TransferEncoding)
+            }
+            match read_chunked(data, &mut i, max_body_length)? {
+                Some(body) => body,
+                None => return Ok(None)
+            }
+        } else {
+            let length = content_length(&headers)?;
+            if length > max_body_length {
+                return Err(ParseError::BodyTooLarge)
+            }
+            if data.len() - i < length {
+                return Ok(None)
+            }
+            i += length;
+            Vec::from(&data[i - length..i])
+        };
+        Ok(Some((Self {
+            method,
+            target: Vec::from(target),
+            path,
+            query,
+            version,
+            headers,
+            body,
+            params: Params::default()
+        }, i)))
+    }
+    pub fn path(&self) -> &[u8] {
+        &self.path
+    }
+    //path of the target still percent-encoded, the way routes are matched
+    pub fn raw_path(&self) -> &[u8] {
+        raw_path(&self.target)
+    }
+    pub fn param(&self, name: &str) -> Option<&[u8]> {
+        self.params.get(name)
+    }
+    //first value of a header, the name is case-insensitive
+    pub fn header(&self, name: &str) -> Option<&[u8]> {
+        self.headers.get(name)
+    }
+    //copy kept for the middlewares while the route takes the request
+    pub(crate) fn without_body(&self) -> Self {
+        Self {
+            method: self.method.clone(),
+            target: self.target.clone(),
+            path: self.path.clone(),
+            query: self.query.clone(),
+            version: self.version,
+            headers: self.headers.clone(),
+            body: vec![],
+            params: self.params.clone()
+        }
+    }
+}
+
+//target without the leading '/' and the query string
+fn raw_path(target: &[u8]) -> &[u8] {
+    let path = match target.iter().position(|c| *c == b'?') {
+        Some(i) => &target[..i],
+        None => target
+    };
+    path.strip_prefix(b"/").unwrap_or(path)
+}
+
+//read a line ending with "\r\n" (or a bare '\n') and move `i` past it
+fn next_line<'a>(data: &'a [u8], i: &mut usize) -> Option<&'a [u8]> {
+    let start = *i;
+    let len = data[start..].iter().position(|c| *c == b'\n')?;
+    *i = start + len + 1;
+    let line = &data[start..start + len];
+    match line.last() {
+        Some(b'\r') => Some(&line[..len - 1]),
+        _ => Some(line)
+    }
+}
+
+fn parse_header(line: &[u8]) -> Result<(&[u8], &[u8]), ParseError> {
+    let colon = line.iter().position(|c| *c == b':').ok_or(ParseError::Header)?;
+    let name = &line[..colon];
+    if name.is_empty() || !name.iter().all(|c| is_token_char(*c)) {
+        return Err(ParseError::Header)
+    }
+    let value = trim(&line[colon + 1..]);
+    if value.iter().any(|c| (*c < b' ' && *c != b'\t') || *c == 0x7f) {
+        return Err(ParseError::Header)
+    }
+    Ok((name, value))
+}
+
+//requests without Content-Length have no body
+fn content_length(headers: &Headers) -> Result<usize, ParseError> {
+    let mut length = None;
+    for value in headers.get_all("Content-Length") {
+        for value in value.split(|c| *c == b',') {
+            let value = trim(value);
+            if value.is_empty() || !value.iter().all(u8::is_ascii_digit) {
+                return Err(ParseError::ContentLength)
+            }
+            let value = String::from_utf8_lossy(value).parse::<usize>().map_err(|_| ParseError::ContentLength)?;
+            //repeated values are only accepted if they are all the same
+            if *length.get_or_insert(value) != value {
+                return Err(ParseError::ContentLength)
+            }
+        }
+    }
+    Ok(length.unwrap_or(0))
+}
+
+//chunked has to be the last transfer coding applied
+fn is_chunked(headers: &Headers) -> bool {
+    match headers.get_all("Transfer-Encoding").flat_map(|v| v.split(|c| *c == b',')).last() {
+        Some(coding) => trim(coding).eq_ignore_ascii_case(b"chunked"),
+        None => false
+    }
+}
+
+fn read_chunked(data: &[u8], i: &mut usize, max_body_length: usize) -> Result<Option<Vec<u8>>, ParseError> {
+    let mut body = vec![];
+    loop {
+        let line = match next_line(data, i) {
+            Some(line) => line,
+            None => return Ok(None)
+        };
+        //chunk extensions after ';' are ignored
+        let size = match line.iter().position(|c| *c == b';') {
+            Some(j) => trim(&line[..j]),
+            None => trim(line)
+        };
+        if size.is_empty() || !size.iter().all(u8::is_ascii_hexdigit) {
+            return Err(ParseError::Chunk)
+        }
+        let size = usize::from_str_radix(&String::from_utf8_lossy(size), 16).map_err(|_| ParseError::BodyTooLarge)?;
+        if size == 0 {
+            //skip the trailer fields
+            loop {
+                match next_line(data, i) {
+                    Some([]) => return Ok(Some(body)),
+                    Some(_) => continue,
+                    None => return Ok(None)
+                }
+            }
+        }
+        //the size comes from the client, written so it can't overflow
+        if size > max_body_length - body.len() {
+            return Err(ParseError::BodyTooLarge)
+        }
+        let end = match i.checked_add(size) {
+            Some(end) if end <= data.len() => end,
+            _ => return Ok(None)
+        };
+        body.extend_from_slice(&data[*i..end]);
+        *i = end;
+        match next_line(data, i) {
+            Some([]) => {},
+            Some(_) => return Err(ParseError::Chunk),
+            None => return Ok(None)
+        }
+    }
+}
+
+fn is_valid_target(method: &Method, target: &[u8]) -> bool {
+    match target {
+        [b'/', ..] => target.iter().all(|c| *c > b' ' && *c != 0x7f),
+        b"*" => *method == Method::Options,
+        _ => false
+    }
+}
+
+fn is_token_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&c)
+}
+
+fn trim(v: &[u8]) -> &[u8] {
+    let is_ows = |c: &u8| *c == b' ' || *c == b'\t';
+    let start = v.iter().position(|c| !is_ows(c)).unwrap_or(v.len());
+    let end = v.iter().rposition(|c| !is_ows(c)).map_or(start, |i| i + 1);
+    &v[start..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(data: &[u8]) -> Result<Option<(Request, usize)>, ParseError> {
+        Request::parse(data, 1024, 64)
+    }
+
+    #[test]
+    fn malformed_request_lines() {
+        assert_eq!(parse(b"GET /\r\n\r\n").err(), Some(ParseError::RequestLine));
+        assert_eq!(parse(b"GET  / HTTP/1.1\r\n\r\n").err(), Some(ParseError::RequestLine));
+        assert_eq!(parse(b"GET / HTTP/1.1 x\r\n\r\n").err(), Some(ParseError::RequestLine));
+        assert_eq!(parse(b"G(T / HTTP/1.1\r\n\r\n").err(), Some(ParseError::Method));
+        assert_eq!(parse(b"GET index HTTP/1.1\r\n\r\n").err(), Some(ParseError::Target));
+        assert_eq!(parse(b"GET * HTTP/1.1\r\n\r\n").err(), Some(ParseError::Target));
+        assert_eq!(parse(b"GET /a%2 HTTP/1.1\r\n\r\n").err(), Some(ParseError::Target));
+        assert_eq!(parse(b"GET /a%zz HTTP/1.1\r\n\r\n").err(), Some(ParseError::Target));
+        assert_eq!(parse(b"GET / HTTP/2.0\r\n\r\n").err(), Some(ParseError::Version));
+        assert_eq!(parse(b"GET / HTTP/1.1\r\nHost\r\n\r\n").err(), Some(ParseError::Header));
+        assert!(parse(b"OPTIONS * HTTP/1.1\r\n\r\n").unwrap().is_some());
+    }
+
+    #[test]
+    fn decoded_path() {
+        let (request, _) = parse(b"GET /%61dmin/a%20b?c=%64 HTTP/1.1\r\n\r\n").unwrap().unwrap();
+        assert_eq!(request.path(), b"admin/a b");
+        assert_eq!(request.raw_path(), b"%61dmin/a%20b");
+        assert_eq!(request.target, b"/%61dmin/a%20b?c=%64");
+        assert_eq!(request.without_body().path(), b"admin/a b");
+    }
+
+    #[test]
+    fn incomplete_requests() {
+        assert!(parse(b"").unwrap().is_none());
+        assert!(parse(b"GET / HTTP/1.1\r\nHost: a\r\n").unwrap().is_none());
+        assert!(parse(b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nab").unwrap().is_none());
+        assert_eq!(Request::parse(&[b'a'; 32], 16, 16).err(), Some(ParseError::RequestLineTooLong));
+        assert_eq!(Request::parse(b"GET /aaaaaaaaaaaaaaaaaaaa HTTP/1.1\r\n", 16, 16).err(), Some(ParseError::RequestLineTooLong));
+        assert_eq!(Request::parse(b"GET / HTTP/1.1\r\nHost: aaaaaaaaaaaa", 24, 16).err(), Some(ParseError::HeadersTooLarge));
+        assert_eq!(Request::parse(b"GET / HTTP/1.1\r\nHost: aaaaaaaaaaaa\r\n\r\n", 24, 16).err(), Some(ParseError::HeadersTooLarge));
+    }
+
+    #[test]
+    fn pipelined_byte_counts() {
+        let data = b"\r\nGET /a HTTP/1.1\r\n\r\nPOST /b HTTP/1.1\r\nContent-Length: 3\r\n\r\nabcGET /c HTTP/1.0\n\n";
+        let (first, i) = parse(data).unwrap().unwrap();
+        assert_eq!((first.path(), i), (&b"a"[..], 21));
+        let (second, j) = parse(&data[i..]).unwrap().unwrap();
+        assert_eq!((second.path(), &second.body[..], j), (&b"b"[..], &b"abc"[..], 42));
+        let (third, k) = parse(&data[i + j..]).unwrap().unwrap();
+        assert_eq!((third.path(), third.version, i + j + k), (&b"c"[..], Version::Http10, data.len()));
+    }
+
+    #[test]
+    fn content_length_conflicts() {
+        let body = |headers: &str| parse(format!("POST / HTTP/1.1\r\n{}\r\nabc", headers).as_bytes()).map(|v| v.map(|(v, _)| v.body));
+        assert_eq!(body("Content-Length: 3\r\nContent-Length: 3\r\n"), Ok(Some(b"abc".to_vec())));
+        assert_eq!(body("Content-Length: 3, 3\r\n"), Ok(Some(b"abc".to_vec())));
+        assert_eq!(body("Content-Length: 3\r\nContent-Length: 2\r\n").err(), Some(ParseError::ContentLength));
+        assert_eq!(body("Content-Length: 3, 2\r\n").err(), Some(ParseError::ContentLength));
+        assert_eq!(body("Content-Length: +3\r\n").err(), Some(ParseError::ContentLength));
+        assert_eq!(body("Content-Length: \r\n").err(), Some(ParseError::ContentLength));
+        assert_eq!(body("Content-Length: 99999999999999999999999\r\n").err(), Some(ParseError::ContentLength));
+        assert_eq!(body("Content-Length: 65\r\n").err(), Some(ParseError::BodyTooLarge));
+    }
+
+    #[test]
+    fn chunked_bodies() {
+        let body = |chunks: &str| parse(format!("POST / HTTP/1.1\r\nTransfer-Encoding: gzip, chunked\r\n\r\n{}", chunks).as_bytes())
+            .map(|v| v.map(|(v, _)| v.body));
+        assert_eq!(body("3;ext=1\r\nabc\r\n2\r\nde\r\n0\r\nTrailer: x\r\n\r\n"), Ok(Some(b"abcde".to_vec())));
+        assert_eq!(body("3\r\nabc\r\n0\r\n"), Ok(None));
+        assert_eq!(body("3\r\nab"), Ok(None));
+        assert_eq!(body("3\r\nabcd\r\n0\r\n\r\n").err(), Some(ParseError::Chunk));
+        assert_eq!(body("x\r\n").err(), Some(ParseError::Chunk));
+        assert_eq!(body("\r\n").err(), Some(ParseError::Chunk));
+        assert_eq!(body("41\r\n").err(), Some(ParseError::BodyTooLarge));
+        assert_eq!(body("20\r\n01234567890123456789012345678901\r\n21\r\n").err(), Some(ParseError::BodyTooLarge));
+        assert_eq!(body("1\r\na\r\nFFFFFFFFFFFFFFFF\r\n").err(), Some(ParseError::BodyTooLarge));
+        assert_eq!(body("10000000000000000\r\n").err(), Some(ParseError::BodyTooLarge));
+        let chunked_first = parse(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked, gzip\r\n\r\n");
+        assert_eq!(chunked_first.err(), Some(ParseError::TransferEncoding));
+        let both = parse(b"POST / HTTP/1.1\r\nContent-Length: 3\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\nGET / HTTP/1.1\r\n\r\n");
+        assert_eq!(both.err(), Some(ParseError::Framing));
+    }
+}
@@ -1,103 +1,113 @@
-#[macro_export]
-macro_rules! get_header_body_utf8 {
-    ($res: expr, $socket: expr) => {
-        match str::from_utf8($res.get_header_body()) {
-            Ok(v) => v,
-            Err(e) => return $socket.send_500(e)
-        }
-    }
-}
-#[macro_export]
-macro_rules! get_body_utf8 {
-    ($res: expr, $socket: expr) => {
-        match str::from_utf8($res.get_body()) {
-            Ok(v) => v,
-            Err(e) => return $socket.send_500(e)
-        }
-    }
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+use super::{FileType, Headers};
+
+pub enum Body {
+    Bytes(Vec<u8>),
+    //read while the connection sends it, with its length if known.
+    //without a length it is sent chunked, or until the connection closes for HTTP/1.0
+    Stream(Box<dyn Read + Send>, Option<u64>)
 }
 
-pub trait Response {
-    fn get_body(&self) -> &[u8];
-    fn get_body_formated(&self) -> Vec<&[u8]>;
-    fn get_header_body(&self) -> &[u8];
-    fn get_header_body_formated(&self) -> Vec<&[u8]>;
+//response of any status, sent with `Socket::send`.
+//"Content-Length", "Transfer-Encoding" and "Connection" are set by the socket
+pub struct Response {
+    pub status: u16,
+    pub headers: Headers,
+    pub body: Body
 }
 
-impl Response for Vec<u8>{
-    fn get_body(&self) -> &[u8] {
-        let mut i = self.len();
-        if i == 0 {return &[]}
-        i -= 1;
-        while self[i] != b'\n' {
-            i -= 1;
+impl Response {
+    pub fn new(status: u16) -> Self {
+        Self {
+            status,
+            headers: Headers::new(),
+            body: Body::Bytes(vec![])
         }
-        i += 1;
-        return &self[i..self.len()]
     }
-    //get values splitted by '|'
-    fn get_body_formated(&self) -> Vec<&[u8]> {
-        let mut res = vec![];
-        let mut i = self.len();
-        if i == 0 {return vec![]}
-        i -= 1;
-        let mut j = i + 1;
-        while self[i] != b'\n' {
-            if self[i] == b'|' {
-                res.push(&self[(i+1)..j]);
-                j = i;
-            }
-            i -= 1;
-        }
-        res.push(&self[(i+1)..j]);
-        res
+    pub fn ok() -> Self {
+        Self::new(200)
     }
-    //get body from 'Body' header
-    fn get_header_body(&self) -> &[u8] {
-        let l = self.len() - 1;
-        let mut i = l;
-        if i == 0 {return &[]}
-        i -= 1;
-        loop  {
-            if self[i] == b'B' && self[i+1] == b'o' && self[i+2] == b'd' && self[i+3] == b'y' {
-                i += 6;
-                let mut j = i;
-                while self[j] != b'\n' && j <= l {
-                    j += 1
-                }
-                return &self[i..j-1]
-            }else if i <= 1 {
-                break
-            }else {
-                i -= 1;
-            }
-        }
-        return &[]
+    //200 response streaming the file, typed by its extension
+    pub fn file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let file = fs::File::open(path)?;
+        let length = file.metadata()?.len();
+        let extension = path.extension().map_or(&b""[..], |v| v.to_str().unwrap_or_default().as_bytes());
+        Ok(Self::ok()
+            .header("Content-Type", FileType::get_by_extension(extension).to_bytes())
+            .stream(file, Some(length)))
     }
-    //get body from 'Body' header splitted by '|'
-    fn get_header_body_formated(&self) -> Vec<&[u8]> {
-        let l = self.len() - 1;
-        let mut i = l;
-        if i == 0 {return vec![]}
-        i -= 1;
-        loop  {
-            if self[i] == b'B' && self[i+1] == b'o' && self[i+2] == b'd' && self[i+3] == b'y' {
-                i += 6;
-                let mut res = vec![];
-                let mut j = i;
-                while self[j] != b'\n' && j <= l {
-                    if self[j] == b'|' {
-                        res.push(&self[i..j-1])
-                    }
-                    j += 1
-                }
-                return res
-            }else if i <= 1 {
-                break
-            }else {
-                i -= 1;
-            }
-        }
-        return vec![]
+    //add a header, keeping the ones with the same name
+    pub fn header<K: AsRef<[u8]>, V: AsRef<[u8]>>(mut self, name: K, value: V) -> Self {
+        self.headers.append(name, value);
+        self
+    }
+    pub fn body<B: Into<Vec<u8>>>(mut self, body: B) -> Self {
+        self.body = Body::Bytes(body.into());
+        self
+    }
+    pub fn stream<R: Read + Send + 'static>(mut self, reader: R, length: Option<u64>) -> Self {
+        self.body = Body::Stream(Box::new(reader), length);
+        self
+    }
+    pub fn reason(&self) -> &'static str {
+        reason_phrase(self.status)
+    }
+    //1xx, 204 and 304 responses never have a body
+    pub fn has_body(&self) -> bool {
+        !(100..200).contains(&self.status) && self.status != 204 && self.status != 304
+    }
+}
+
+pub fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        100 => "Continue",
+        101 => "Switching Protocols",
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        203 => "Non-Authoritative Information",
+        204 => "No Content",
+        205 => "Reset Content",
+        206 => "Partial Content",
+        300 => "Multiple Choices",
+        301 => "Moved Permanently",
+        302 => "Found",
+        303 => "See Other",
+        304 => "Not Modified",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        402 => "Payment Required",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        406 => "Not Acceptable",
+        407 => "Proxy Authentication Required",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        410 => "Gone",
+        411 => "Length Required",
+        412 => "Precondition Failed",
+        413 => "Payload Too Large",
+        414 => "URI Too Long",
+        415 => "Unsupported Media Type",
+        416 => "Range Not Satisfiable",
+        417 => "Expectation Failed",
+        422 => "Unprocessable Entity",
+        426 => "Upgrade Required",
+        428 => "Precondition Required",
+        429 => "Too Many Requests",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        505 => "HTTP Version Not Supported",
+        _ => ""
     }
 }
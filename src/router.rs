@@ -0,0 +1,240 @@
+use std::collections::HashMap;
+use super::util::percent_decode;
+
+//values captured by ':name' and '*name' segments of a route
+#[derive(Default, Debug, Clone)]
+pub struct Params(Vec<(Vec<u8>, Vec<u8>)>);
+
+impl Params {
+    pub fn get(&self, name: &str) -> Option<&[u8]> {
+        self.0.iter()
+            .find(|(k, _)| k == name.as_bytes())
+            .map(|(_, v)| v.as_slice())
+    }
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], &[u8])> {
+        self.0.iter().map(|(k, v)| (k.as_slice(), v.as_slice()))
+    }
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+#[derive(Default, Clone)]
+struct Node {
+    statics: HashMap<Vec<u8>, Node>,
+    param: Option<(Vec<u8>, Box<Node>)>,
+    wildcard: Option<(Vec<u8>, usize)>,
+    value: Option<usize>
+}
+
+//segment trie, on each segment static matches are tried first, then ':param' and then '*wildcard'
+#[derive(Clone)]
+pub(crate) struct Router<V> {
+    root: Node,
+    values: Vec<V>
+}
+
+impl<V> Default for Router<V> {
+    fn default() -> Self {
+        Self {
+            root: Node::default(),
+            values: vec![]
+        }
+    }
+}
+
+impl<V> Router<V> {
+    pub fn insert(&mut self, path: &[u8], value: V) {
+        let path = path.strip_prefix(b"/").unwrap_or(path);
+        let index = self.values.len();
+        let mut node = &mut self.root;
+        let mut segments = path.split(|c| *c == b'/').peekable();
+        while let Some(segment) = segments.next() {
+            match segment.first() {
+                Some(b':') => {
+                    let name = &segment[1..];
+                    let (current, next) = node.param.get_or_insert_with(|| (Vec::from(name), Box::default()));
+                    if current != name {
+                        panic!("Route \"{}\" conflicts with parameter \":{}\"",
+                            String::from_utf8_lossy(path), String::from_utf8_lossy(current))
+                    }
+                    node = next;
+                },
+                Some(b'*') => {
+                    if segments.peek().is_some() {
+                        panic!("Wildcard must be the last segment of route \"{}\"", String::from_utf8_lossy(path))
+                    }
+                    let name = &segment[1..];
+                    match node.wildcard {
+                        Some((ref current, _)) if current != name =>
+                            panic!("Route \"{}\" conflicts with wildcard \"*{}\"",
+                                String::from_utf8_lossy(path), String::from_utf8_lossy(current)),
+                        Some((_, i)) => self.values[i] = value,
+                        None => {
+                            node.wildcard = Some((Vec::from(name), index));
+                            self.values.push(value);
+                        }
+                    }
+                    return
+                },
+                _ => node = node.statics.entry(Vec::from(segment)).or_default()
+            }
+        }
+        match node.value {
+            Some(i) => self.values[i] = value,
+            None => {
+                node.value = Some(index);
+                self.values.push(value);
+            }
+        }
+    }
+    //value of the percent-encoded path, with the decoded captures
+    pub fn find(&self, path: &[u8]) -> Option<(&V, Params)> {
+        let (index, params) = self.find_index(path)?;
+        Some((&self.values[index], params))
+    }
+    pub fn find_mut(&mut self, path: &[u8]) -> Option<(&mut V, Params)> {
+        let (index, params) = self.find_index(path)?;
+        Some((&mut self.values[index], params))
+    }
+    fn find_index(&self, path: &[u8]) -> Option<(usize, Params)> {
+        let path = path.strip_prefix(b"/").unwrap_or(path);
+        let mut params = Params::default();
+        let index = find(&self.root, path, &mut params)?;
+        Some((index, params))
+    }
+}
+
+//the path is still percent-encoded, each segment is decoded once it is split
+//so an encoded '/' is part of a segment
+fn find(node: &Node, path: &[u8], params: &mut Params) -> Option<usize> {
+    let (segment, rest) = match path.iter().position(|c| *c == b'/') {
+        Some(i) => (&path[..i], Some(&path[i + 1..])),
+        None => (path, None)
+    };
+    let segment = percent_decode(segment)?;
+    let segment = segment.as_slice();
+    let next = |node: &Node, params: &mut Params| match rest {
+        Some(rest) => find(node, rest, params),
+        None => node.value
+    };
+    if let Some(node) = node.statics.get(segment) {
+        if let Some(index) = next(node, params) {
+            return Some(index)
+        }
+    }
+    if let Some((name, node)) = &node.param {
+        if !segment.is_empty() {
+            params.0.push((name.clone(), Vec::from(segment)));
+            if let Some(index) = next(node, params) {
+                return Some(index)
+            }
+            params.0.pop();
+        }
+    }
+    if let Some((name, index)) = &node.wildcard {
+        params.0.push((name.clone(), percent_decode(path)?));
+        return Some(*index)
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build(paths: &[&str]) -> Router<usize> {
+        let mut router = Router::default();
+        for (i, path) in paths.iter().enumerate() {
+            router.insert(path.as_bytes(), i);
+        }
+        router
+    }
+
+    fn find(router: &Router<usize>, path: &str) -> Option<(usize, Vec<(String, String)>)> {
+        let (value, params) = router.find(path.as_bytes())?;
+        let params = params.iter()
+            .map(|(k, v)| (String::from_utf8_lossy(k).into_owned(), String::from_utf8_lossy(v).into_owned()))
+            .collect();
+        Some((*value, params))
+    }
+
+    fn param(name: &str, value: &str) -> (String, String) {
+        (name.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn static_before_param_before_wildcard() {
+        let router = build(&["users/*rest", "users/:id", "users/me"]);
+        assert_eq!(find(&router, "users/me"), Some((2, vec![])));
+        assert_eq!(find(&router, "/users/42"), Some((1, vec![param("id", "42")])));
+        assert_eq!(find(&router, "users/42/posts"), Some((0, vec![param("rest", "42/posts")])));
+        assert_eq!(find(&router, "users"), None);
+        assert_eq!(find(&router, "posts/1"), None);
+    }
+
+    #[test]
+    fn backtracks_on_failed_deeper_match() {
+        let router = build(&["a/b/c", "a/*rest"]);
+        assert_eq!(find(&router, "a/b/c"), Some((0, vec![])));
+        assert_eq!(find(&router, "a/b/x"), Some((1, vec![param("rest", "b/x")])));
+        //params captured on the abandoned branch are dropped
+        let router = build(&["a/:x/c", "a/:x/d/e", "*all"]);
+        assert_eq!(find(&router, "a/b/d/e"), Some((1, vec![param("x", "b")])));
+        assert_eq!(find(&router, "a/b/d/f"), Some((2, vec![param("all", "a/b/d/f")])));
+    }
+
+    #[test]
+    fn empty_segments() {
+        let router = build(&["", "a/:id", "b/*rest", "c//d"]);
+        assert_eq!(find(&router, ""), Some((0, vec![])));
+        assert_eq!(find(&router, "/"), Some((0, vec![])));
+        //params don't match empty segments, wildcards match an empty rest
+        assert_eq!(find(&router, "a/"), None);
+        assert_eq!(find(&router, "b/"), Some((2, vec![param("rest", "")])));
+        assert_eq!(find(&router, "c//d"), Some((3, vec![])));
+        assert_eq!(find(&router, "c/d"), None);
+    }
+
+    #[test]
+    fn decodes_segments_after_splitting() {
+        let router = build(&["p/:id", "a b/c", "w/*rest"]);
+        assert_eq!(find(&router, "p/a%2Fb"), Some((0, vec![param("id", "a/b")])));
+        assert_eq!(find(&router, "a%20b/%63"), Some((1, vec![])));
+        assert_eq!(find(&router, "a%20b%2Fc"), None);
+        assert_eq!(find(&router, "w/x%2Fy/z%20"), Some((2, vec![param("rest", "x/y/z ")])));
+        assert_eq!(find(&router, "p/%zz"), None);
+    }
+
+    #[test]
+    fn reinserting_replaces_the_value() {
+        let mut router = build(&["a", "b/:id", "c/*rest"]);
+        router.insert(b"a", 5);
+        router.insert(b"b/:id", 6);
+        router.insert(b"c/*rest", 7);
+        assert_eq!(find(&router, "a"), Some((5, vec![])));
+        assert_eq!(find(&router, "b/1"), Some((6, vec![param("id", "1")])));
+        assert_eq!(find(&router, "c/d"), Some((7, vec![param("rest", "d")])));
+    }
+
+    #[test]
+    #[should_panic(expected = "conflicts with parameter \":id\"")]
+    fn conflicting_param_names() {
+        build(&["users/:id", "users/:name/posts"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "conflicts with wildcard \"*rest\"")]
+    fn conflicting_wildcard_names() {
+        build(&["c/*rest", "c/*path"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Wildcard must be the last segment")]
+    fn wildcard_not_last() {
+        build(&["files/*path/raw"]);
+    }
+}
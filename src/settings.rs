@@ -1,25 +1,139 @@
+use std::sync::Arc;
+use std::time::Duration;
+use super::{util, Method};
+
 pub const LISTENER_EVENT_TOKEN: mio::Token = mio::Token(0);
+pub const WAKER_EVENT_TOKEN: mio::Token = mio::Token(usize::MAX);
+
+pub enum CacheControlPattern {
+    //file extension without the dot, like "css"
+    Extension(&'static str),
+    //path relative to the root folder where '*' and '?' don't match '/' and "**" matches folders,
+    //like "assets/**" or "**/*.min.js"
+    Glob(&'static str)
+}
+
+//"Cache-Control" value of the static files matching the pattern
+pub struct CacheControlRule {
+    pub pattern: CacheControlPattern,
+    pub value: &'static str
+}
+impl CacheControlRule {
+    pub fn extension(extension: &'static str, value: &'static str) -> Self {
+        Self {
+            pattern: CacheControlPattern::Extension(extension),
+            value
+        }
+    }
+    pub fn glob(glob: &'static str, value: &'static str) -> Self {
+        Self {
+            pattern: CacheControlPattern::Glob(glob),
+            value
+        }
+    }
+    pub fn matches(&self, path: &[u8]) -> bool {
+        match self.pattern {
+            CacheControlPattern::Extension(extension) => util::get_extension(path).eq_ignore_ascii_case(extension.as_bytes()),
+            CacheControlPattern::Glob(glob) => util::glob_match(glob.as_bytes(), path)
+        }
+    }
+}
 
 pub struct StaticFilesSettings {
     pub root_path: &'static str,
-    pub enable_cache: bool
+    pub enable_cache: bool,
+    //file inside the root folder sent as the body of not found responses, like "404.html"
+    pub not_found_page: Option<&'static str>,
+    //the first matching rule sets the "Cache-Control" header of a file
+    pub cache_control: Vec<CacheControlRule>
 }
 impl Default for StaticFilesSettings {
     fn default() -> Self {
         Self {
             root_path: "public",
-            enable_cache: false
+            enable_cache: false,
+            not_found_page: None,
+            cache_control: vec![]
         }
     }
 }
 
+#[derive(Clone)]
 pub struct SocketSettings {
-    pub max_payloads: usize
+    //max length of the request line and headers, bigger requests receive a 413
+    pub max_header_length: usize,
+    //max length of the request body, bigger requests receive a 413
+    pub max_body_length: usize,
+    //persistent connections without activity for this long are closed
+    pub idle_timeout: Duration,
+    //requests served on a connection before it is closed, 1 disables keep-alive
+    pub max_requests: usize,
+    //responses waiting to be sent on a connection, its next requests are answered once the client reads them
+    pub max_pipeline_depth: usize
 }
 impl Default for SocketSettings {
     fn default() -> Self {
         Self {
-            max_payloads: 5
+            max_header_length: 8 * 1024,
+            max_body_length: 1024 * 1024,
+            idle_timeout: Duration::from_secs(5),
+            max_requests: 100,
+            max_pipeline_depth: 16
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct CorsSettings {
+    //origins allowed to read the responses, "*" allows every origin
+    pub allowed_origins: Vec<&'static str>,
+    //methods allowed in preflight responses
+    pub allowed_methods: Vec<Method>,
+    //request headers allowed in preflight responses, "*" allows the ones asked for
+    pub allowed_headers: Vec<&'static str>,
+    //let the browser send cookies and authorization, the origin is sent back instead of "*"
+    pub allow_credentials: bool,
+    //time the browser may cache a preflight response
+    pub max_age: Option<Duration>
+}
+impl Default for CorsSettings {
+    fn default() -> Self {
+        Self {
+            allowed_origins: vec!["*"],
+            allowed_methods: vec![Method::Get, Method::Head, Method::Post, Method::Put, Method::Delete, Method::Patch],
+            allowed_headers: vec![],
+            allow_credentials: false,
+            max_age: None
+        }
+    }
+}
+
+pub enum AccessLogFormat {
+    //host ident user [time] "request line" status bytes
+    Common,
+    //common format followed by "referer" "user agent"
+    Combined,
+    //one json object per line, with the latency of the response
+    Json
+}
+
+pub enum AccessLogSink {
+    Stdout,
+    //path of a file the lines are appended to, created if it doesn't exist
+    File(&'static str),
+    //called with every line, without the line break
+    Callback(Arc<dyn Fn(&str) + Send + Sync>)
+}
+
+pub struct AccessLogSettings {
+    pub format: AccessLogFormat,
+    pub sink: AccessLogSink
+}
+impl Default for AccessLogSettings {
+    fn default() -> Self {
+        Self {
+            format: AccessLogFormat::Common,
+            sink: AccessLogSink::Stdout
         }
     }
 }
@@ -27,16 +141,28 @@ impl Default for SocketSettings {
 pub struct Settings {
     pub address: [u8; 4],
     pub port: u16,
+    //threads running an event loop, with more than one a separate thread accepts the connections
+    pub workers: usize,
     pub socket: SocketSettings,
-    pub static_files: Option<StaticFilesSettings>
+    pub static_files: Option<StaticFilesSettings>,
+    //cross-origin requests are only allowed by the browsers if this is set
+    pub cors: Option<CorsSettings>,
+    //a line is logged for every response
+    pub access_log: Option<AccessLogSettings>,
+    //time given to pending responses after a shutdown before the connections are dropped
+    pub shutdown_timeout: Duration
 }
 impl Default for Settings {
     fn default() -> Self {
         Self {
             address: [127,0,0,1],
             port: 3000,
+            workers: 1,
             socket: SocketSettings::default(),
-            static_files: Some(Default::default())
+            static_files: Some(Default::default()),
+            cors: None,
+            access_log: None,
+            shutdown_timeout: Duration::from_secs(10)
         }
     }
 }
\ No newline at end of file
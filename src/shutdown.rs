@@ -0,0 +1,45 @@
+use mio::Waker;
+use std::sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}};
+
+#[derive(Default)]
+struct Shared {
+    requested: AtomicBool,
+    //set once the server is running
+    waker: Mutex<Option<Waker>>
+}
+
+//stops a running server from any thread: the listener is closed, pending responses are
+//sent until the shutdown timeout and then `Server::run` returns
+#[derive(Clone, Default)]
+pub struct ShutdownHandle(Arc<Shared>);
+
+impl ShutdownHandle {
+    pub fn shutdown(&self) {
+        self.0.requested.store(true, Ordering::SeqCst);
+        self.wake();
+    }
+    pub fn is_shutdown(&self) -> bool {
+        self.0.requested.load(Ordering::SeqCst)
+    }
+    //shutdown when the process receives SIGTERM or SIGINT
+    #[cfg(feature = "signals")]
+    pub fn shutdown_on_signals(&self) -> std::io::Result<()> {
+        use signal_hook::consts::{SIGINT, SIGTERM};
+        let mut signals = signal_hook::iterator::Signals::new([SIGTERM, SIGINT])?;
+        let handle = self.clone();
+        std::thread::spawn(move || {
+            if signals.forever().next().is_some() {
+                handle.shutdown();
+            }
+        });
+        Ok(())
+    }
+    pub(crate) fn wake(&self) {
+        if let Some(waker) = &*self.0.waker.lock().unwrap() {
+            let _ = waker.wake();
+        }
+    }
+    pub(crate) fn set_waker(&self, waker: Waker) {
+        *self.0.waker.lock().unwrap() = Some(waker);
+    }
+}
@@ -1,132 +1,618 @@
 use mio::{Interest, Token};
 use mio::net::TcpStream;
-use std::collections::HashMap;
-use std::io::{Read, Write, ErrorKind::WouldBlock};
-use std::net::{Shutdown};
-use super::File;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, VecDeque};
+use std::io::{self, Read, Write, ErrorKind::{WouldBlock, Interrupted, WriteZero, UnexpectedEof}};
+use std::net::{Shutdown, SocketAddr};
+use std::time::{Duration, Instant};
+use super::{File, Request, ParseError, Version, Response, Body};
+use super::access_log::{AccessLog, Entry};
 
-const MAX_PAYLOAD_LENGTH: usize = 128;
+const READ_BUFFER_LENGTH: usize = 4096;
+//bytes read from a streamed body at once
+const STREAM_CHUNK_LENGTH: usize = 64 * 1024;
 
-const RES_200_0: &'static[u8] = b"HTTP/1.1 200 OK\r\nContent-Length: ";
-const RES_200_1: &'static[u8] = b"\r\n\r\n";
-const RES_200_LENGTH: usize = RES_200_0.len() + RES_200_1.len();
+const CONNECTION_KEEP_ALIVE: &[u8] = b"Connection: keep-alive\r\n";
+const CONNECTION_CLOSE: &[u8] = b"Connection: close\r\n";
 
-const RES_400_0: &'static[u8] = b"HTTP/1.1 400 BadRequest\r\nContent-Length: ";
-const RES_400_1: &'static[u8] = b"\r\n\r\n";
-const RES_400_LENGTH: usize = RES_400_0.len() + RES_400_1.len();
-
-const RES_404: &'static[u8] = b"HTTP/1.1 404 NotFound\r\n\r\n";
+//streamed body, read once the output before it was sent
+struct Stream {
+    reader: Box<dyn Read + Send>,
+    //bytes left to read when the length was sent
+    remaining: Option<u64>,
+    chunked: bool,
+    done: bool
+}
 
-const RES_500_0: &'static[u8] = b"HTTP/1.1 500 BadRequest\r\nContent-Length: ";
-const RES_500_1: &'static[u8] = b"\r\n\r\n";
-const RES_500_LENGTH: usize = RES_500_0.len() + RES_500_1.len();
+impl Stream {
+    //next part of the body to send, framed as a chunk if needed, None once the body is complete
+    fn next(&mut self) -> io::Result<Option<Vec<u8>>> {
+        if self.done || self.remaining == Some(0) {
+            return Ok(None)
+        }
+        let length = self.remaining.map_or(STREAM_CHUNK_LENGTH, |remaining| remaining.min(STREAM_CHUNK_LENGTH as u64) as usize);
+        let mut data = vec![0; length];
+        let length = loop {
+            match self.reader.read(&mut data) {
+                Ok(length) => break length,
+                Err(ref e) if e.kind() == Interrupted => continue,
+                Err(e) => return Err(e)
+            }
+        };
+        if length == 0 {
+            //the sent length can't be kept
+            if self.remaining.is_some() {
+                return Err(UnexpectedEof.into())
+            }
+            self.done = true;
+            return Ok(if self.chunked {Some(b"0\r\n\r\n".to_vec())} else {None})
+        }
+        data.truncate(length);
+        if let Some(remaining) = &mut self.remaining {
+            *remaining -= length as u64;
+        }
+        if self.chunked {
+            let mut chunk = format!("{:X}\r\n", length).into_bytes();
+            chunk.extend(data);
+            chunk.extend(b"\r\n");
+            return Ok(Some(chunk))
+        }
+        Ok(Some(data))
+    }
+}
 
-const RES_FILE_0: &'static[u8] = b"HTTP/1.1 200 OK\r\nAccess-Control-Allow-Origin: *\r\nContent-Type: ";
-const RES_FILE_1: &'static[u8] = b"\r\nContent-Length: ";
-const RES_FILE_2: &'static[u8] = b"\r\n\r\n";
-const RES_FILE_LENGTH: usize = RES_FILE_0.len() + RES_FILE_1.len() + RES_FILE_2.len();
+enum Output {
+    Bytes(Vec<u8>),
+    Stream(Stream)
+}
 
 pub struct Socket {
     pub stream: TcpStream,
-    pub max_payloads: usize
+    address: SocketAddr,
+    //answering a HEAD request, responses are sent without body
+    pub(crate) head: bool,
+    //keep the connection open after answering the current request
+    pub(crate) keep_alive: bool,
+    //a response was sent for the current request
+    pub(crate) responded: bool,
+    //close the connection once the output is flushed
+    pub(crate) closing: bool,
+    //the current request is answered by an async route that didn't complete yet
+    pub(crate) waiting: bool,
+    //the current request as seen by the route, kept for the middlewares
+    pub(crate) request: Option<Request>,
+    //middlewares whose `before` ran for the current request, only their `after` runs
+    pub(crate) middlewares: usize,
+    //response of the current request, written once the middlewares decorated it
+    pub(crate) response: Option<Response>,
+    //received bytes that are not part of a dispatched request yet
+    buffer: Vec<u8>,
+    //bytes may be left to read, they are read once the connection can answer its next requests
+    pub(crate) readable: bool,
+    //HTTP version of the current request
+    version: Version,
+    //responses waiting for the kernel buffer, `written` bytes of the first one were already sent
+    output: VecDeque<Output>,
+    written: usize,
+    //registered for writable events
+    writable: bool,
+    last_activity: Instant,
+    requests: usize,
+    //responses written since the output was last sent completely
+    unsent: usize,
+    max_header_length: usize,
+    max_body_length: usize,
+    max_requests: usize,
+    max_pipeline_depth: usize,
+    access_log: Option<AccessLog>,
+    //current request, logged with its response
+    log_entry: Option<Entry>
 }
 
 impl Socket {
-    pub fn read_stream(&mut self) -> Option<Vec<u8>> {
-        let mut res: Vec<u8> = vec![];
-        let mut buff = [0 as u8; MAX_PAYLOAD_LENGTH];
-        let mut i = 0;
-        loop {
+    //read what is available into the buffer, up to the longest request accepted,
+    //returns false if the connection was closed
+    pub fn read_stream(&mut self) -> bool {
+        let mut buff = [0u8; READ_BUFFER_LENGTH];
+        while self.buffer.len() < self.max_buffer_length() {
             match self.stream.read(&mut buff) {
+                Ok(0) => return false,
                 Ok(len) => {
-                    res.extend_from_slice(&buff[0..len]);
-                    i += 1;
-                    if len == 0 || i >= self.max_payloads {
-                        break
+                    self.buffer.extend_from_slice(&buff[0..len]);
+                    self.last_activity = Instant::now();
+                },
+                Err(ref e) if e.kind() == WouldBlock => {
+                    self.readable = false;
+                    return true
+                },
+                Err(ref e) if e.kind() == Interrupted => continue,
+                Err(_) => return false
+            }
+        }
+        true
+    }
+    //take the next complete request out of the buffer, None if it didn't fully arrive yet
+    pub fn next_request(&mut self) -> Result<Option<Request>, ParseError> {
+        match Request::parse(&self.buffer, self.max_header_length, self.max_body_length)? {
+            Some((request, length)) => {
+                self.buffer.drain(..length);
+                Ok(Some(request))
+            },
+            //a full buffer without a complete request, like a chunked body with a lot of framing
+            None if self.buffer.len() >= self.max_buffer_length() => Err(ParseError::BodyTooLarge),
+            None => Ok(None)
+        }
+    }
+    fn max_buffer_length(&self) -> usize {
+        self.max_header_length.saturating_add(self.max_body_length)
+    }
+    //close the connection after a request that couldn't be parsed, returns the response to answer it with
+    pub(crate) fn reject(&mut self, e: ParseError) -> Response {
+        self.head = false;
+        self.keep_alive = false;
+        self.closing = true;
+        self.request = None;
+        match e {
+            ParseError::RequestLineTooLong => Response::new(414),
+            ParseError::HeadersTooLarge => Response::new(431),
+            ParseError::BodyTooLarge => Response::new(413),
+            e => Response::new(400).body(e.to_string())
+        }
+    }
+    //close the connection after the response of the current request unless it is kept alive
+    pub(crate) fn finish_request(&mut self) {
+        if !self.keep_alive {
+            self.closing = true;
+        }
+    }
+    //HTTP/1.1 connections are persistent unless "Connection: close" is sent,
+    //HTTP/1.0 connections only if "Connection: keep-alive" is sent
+    pub(crate) fn start_request(&mut self, request: &Request) {
+        self.requests += 1;
+        self.head = request.method == crate::Method::Head;
+        self.version = request.version;
+        if self.access_log.is_some() {
+            self.log_entry = Some(Entry::new(request));
+        }
+        self.responded = false;
+        let has_option = |option: &[u8]| request.headers.get_all("Connection")
+            .flat_map(|v| v.split(|c| *c == b','))
+            .any(|v| v.trim_ascii().eq_ignore_ascii_case(option));
+        self.keep_alive = self.requests < self.max_requests && match request.version {
+            Version::Http11 => !has_option(b"close"),
+            Version::Http10 => has_option(b"keep-alive")
+        };
+    }
+    //the client doesn't read its responses, the next requests wait in the buffer until it does
+    pub(crate) fn is_full(&self) -> bool {
+        self.unsent >= self.max_pipeline_depth.max(1)
+    }
+    //no requests are answered until the pending route completes or the client reads its responses,
+    //the connection isn't read meanwhile so its buffer stays bounded
+    pub(crate) fn is_paused(&self) -> bool {
+        self.waiting || self.is_full()
+    }
+    //answer the current request, the response is written after the middlewares.
+    //a request gets a single response, sending again replaces the previous one
+    pub fn send(&mut self, response: Response) {
+        self.response = Some(response);
+        self.responded = true;
+    }
+    //write the response with the length of its body and the connection header,
+    //a "Connection: close" header set by the route closes the connection after it
+    pub(crate) fn write_response(&mut self, response: Response) {
+        let has_body = response.has_body();
+        let Response {status, mut headers, body} = response;
+        //length of the body for the access log
+        let mut length = Some(0);
+        if headers.get_all("Connection").any(|v| v.split(|c| *c == b',').any(|v| v.trim_ascii().eq_ignore_ascii_case(b"close"))) {
+            self.keep_alive = false;
+        }
+        headers.remove("Connection");
+        headers.remove("Content-Length");
+        headers.remove("Transfer-Encoding");
+        let mut stream = None;
+        if has_body {
+            match body {
+                Body::Bytes(bytes) => {
+                    headers.set("Content-Length", bytes.len().to_string());
+                    if !self.head {
+                        length = Some(bytes.len() as u64);
+                        stream = Some(Output::Bytes(bytes));
                     }
                 },
-                Err(ref e) => if e.kind() == WouldBlock {
-                    break
-                } else {
-                    return None
+                Body::Stream(reader, body_length) => {
+                    let chunked = body_length.is_none() && self.version == Version::Http11;
+                    match body_length {
+                        Some(length) => headers.set("Content-Length", length.to_string()),
+                        None if chunked => headers.set("Transfer-Encoding", "chunked"),
+                        //HTTP/1.0 clients read the body until the connection closes
+                        None => self.keep_alive = false
+                    }
+                    if !self.head {
+                        length = body_length;
+                        stream = Some(Output::Stream(Stream {reader, remaining: body_length, chunked, done: false}));
+                    }
                 }
             }
         }
-        return Some(res)
+        let mut head = format!("HTTP/1.1 {} {}\r\n", status, crate::reason_phrase(status)).into_bytes();
+        head.extend(if self.keep_alive {CONNECTION_KEEP_ALIVE} else {CONNECTION_CLOSE});
+        for (name, value) in headers.iter() {
+            head.extend(name);
+            head.extend(b": ");
+            head.extend(value);
+            head.extend(b"\r\n");
+        }
+        head.extend(b"\r\n");
+        self.write(head);
+        match stream {
+            Some(Output::Bytes(bytes)) => self.write(bytes),
+            Some(stream) => self.output.push_back(stream),
+            None => ()
+        }
+        self.unsent += 1;
+        if let Some(access_log) = &self.access_log {
+            access_log.write(self.address, self.log_entry.take(), status, length);
+        }
     }
     pub fn send_file(&mut self, file: &File) {
-        let mut send: Vec<u8> = Vec::with_capacity(RES_FILE_LENGTH + file.0.len());
-        send.extend(RES_FILE_0);
-        send.extend(file.0);
-        send.extend(RES_FILE_1);
-        send.extend(file.1.len().to_string().as_bytes());
-        send.extend(RES_FILE_2);
-        send.extend(&file.1);
-        if let Err(_) = self.stream.write_all(&send) {};
+        self.send(file.response());
     }
     pub fn send_200(&mut self, data: &[u8]) {
-        let mut send: Vec<u8> = Vec::with_capacity(RES_200_LENGTH + data.len());
-        send.extend(RES_200_0);
-        send.extend(data.len().to_string().as_bytes());
-        send.extend(RES_200_1);
-        send.extend(data);
-        if let Err(_) = self.stream.write_all(&send) {};
+        self.send(Response::ok().body(data));
     }
     pub fn send_400(&mut self, data: &[u8]) {
-        let mut send: Vec<u8> = Vec::with_capacity(RES_400_LENGTH + data.len());
-        send.extend(RES_400_0);
-        send.extend(data.len().to_string().as_bytes());
-        send.extend(RES_400_1);
-        send.extend(data);
-        if let Err(_) = self.stream.write_all(&send) {};
+        self.send(Response::new(400).body(data));
     }
     pub fn send_404(&mut self) {
-        if let Err(_) = self.stream.write_all(RES_404) {};
+        self.send(Response::new(404));
+    }
+    pub fn send_413(&mut self) {
+        self.send(Response::new(413));
     }
     pub fn send_500(&mut self, data: impl std::fmt::Display) {
-        let data = data.to_string();
-        let data = data.as_bytes();
-        let mut send: Vec<u8> = Vec::with_capacity(RES_500_LENGTH + data.len());
-        send.extend(RES_500_0);
-        send.extend(data.len().to_string().as_bytes());
-        send.extend(RES_500_1);
-        send.extend(data);
-        if let Err(_) = self.stream.write_all(&send) {};
+        self.send(Response::new(500).body(data.to_string()));
+    }
+    fn write(&mut self, bytes: Vec<u8>) {
+        match self.output.back_mut() {
+            Some(Output::Bytes(output)) => output.extend(bytes),
+            _ => self.output.push_back(Output::Bytes(bytes))
+        }
+    }
+    fn can_close(&self) -> bool {
+        self.closing && !self.waiting
     }
+    //write as much of the output as the kernel accepts, reading streamed bodies as it goes,
+    //returns true once everything was sent
+    fn flush(&mut self) -> io::Result<bool> {
+        loop {
+            let chunk = match self.output.front_mut() {
+                Some(Output::Bytes(bytes)) => {
+                    while self.written < bytes.len() {
+                        match self.stream.write(&bytes[self.written..]) {
+                            Ok(0) => return Err(WriteZero.into()),
+                            Ok(len) => {
+                                self.written += len;
+                                self.last_activity = Instant::now();
+                            },
+                            Err(ref e) if e.kind() == WouldBlock => return Ok(false),
+                            Err(ref e) if e.kind() == Interrupted => continue,
+                            Err(e) => return Err(e)
+                        }
+                    }
+                    self.written = 0;
+                    None
+                },
+                Some(Output::Stream(stream)) => stream.next()?,
+                None => {
+                    self.unsent = 0;
+                    return Ok(true)
+                }
+            };
+            //the next part of a stream goes before it, a sent output or a complete stream is removed
+            match chunk {
+                Some(chunk) => self.output.push_front(Output::Bytes(chunk)),
+                None => {
+                    self.output.pop_front();
+                }
+            }
+        }
+    }
+}
+
+//tokens are the slot index (plus one, token 0 is the listener) in the low bits and the
+//slot generation in the high bits, so events of a closed connection never reach the next one.
+//generations wrap before INDEX_MASK so no token is WAKER_EVENT_TOKEN
+const INDEX_BITS: u32 = usize::BITS / 2;
+const INDEX_MASK: usize = (1 << INDEX_BITS) - 1;
+
+struct Slot {
+    generation: usize,
+    socket: Option<Socket>
 }
 
 pub struct Sockets {
-    sockets: HashMap<usize, Socket>,
-    settings: crate::SocketSettings
+    slots: Vec<Slot>,
+    //indexes of the empty slots
+    free: Vec<usize>,
+    //time each connection may be idle until, earliest first. an entry is only checked once it is due,
+    //entries of closed connections are skipped then and the others moved to their new deadline
+    deadlines: BinaryHeap<Reverse<(Instant, usize)>>,
+    settings: crate::SocketSettings,
+    access_log: Option<AccessLog>
 }
 
 impl Sockets {
-    pub fn new(settings: crate::SocketSettings) -> Self {
+    pub(crate) fn new(settings: crate::SocketSettings, access_log: Option<AccessLog>) -> Self {
         Self {
-            sockets: HashMap::new(),
-            settings
-        }
-    }
-    pub fn insert(&mut self, poll: &mio::Poll, mut stream: TcpStream) {
-        let token = self.sockets.len() + 1;
-        poll.registry().register(
-            &mut stream,
-            Token(token),
-            Interest::READABLE
-        ).unwrap();
-        self.sockets.insert(token, Socket {
+            slots: vec![],
+            free: vec![],
+            deadlines: BinaryHeap::new(),
+            settings,
+            access_log
+        }
+    }
+    pub fn insert(&mut self, poll: &mio::Poll, mut stream: TcpStream, address: SocketAddr) -> io::Result<()> {
+        let index = self.allocate(INDEX_MASK)?;
+        let slot = &mut self.slots[index];
+        let token = to_token(index, slot.generation);
+        if let Err(e) = poll.registry().register(&mut stream, Token(token), Interest::READABLE) {
+            self.free.push(index);
+            return Err(e)
+        }
+        slot.socket = Some(Socket {
             stream,
-            max_payloads: self.settings.max_payloads
+            address,
+            head: false,
+            keep_alive: false,
+            responded: false,
+            closing: false,
+            waiting: false,
+            request: None,
+            middlewares: 0,
+            response: None,
+            buffer: vec![],
+            readable: false,
+            version: Version::Http11,
+            output: VecDeque::new(),
+            written: 0,
+            writable: false,
+            last_activity: Instant::now(),
+            requests: 0,
+            unsent: 0,
+            max_header_length: self.settings.max_header_length,
+            max_body_length: self.settings.max_body_length,
+            max_requests: self.settings.max_requests,
+            max_pipeline_depth: self.settings.max_pipeline_depth,
+            access_log: self.access_log.clone(),
+            log_entry: None
         });
+        self.deadlines.push(Reverse((Instant::now() + self.settings.idle_timeout, token)));
+        Ok(())
+    }
+    //index of an empty slot, an error once the `max` slots are used as the index must fit in the token
+    fn allocate(&mut self, max: usize) -> io::Result<usize> {
+        if let Some(index) = self.free.pop() {
+            return Ok(index)
+        }
+        if self.slots.len() >= max {
+            return Err(io::Error::other("too many connections"))
+        }
+        self.slots.push(Slot {generation: 0, socket: None});
+        Ok(self.slots.len() - 1)
     }
     pub fn remove(&mut self, poll: &mio::Poll, token: usize) {
-        if let Some(socket) = self.sockets.get_mut(&token) {
-            poll.registry().deregister(&mut socket.stream).unwrap();
-            if let Err(_) = socket.stream.shutdown(Shutdown::Both) {};
+        if let Some(socket) = self.get_mut(token) {
+            let _ = poll.registry().deregister(&mut socket.stream);
+            let _ = socket.stream.shutdown(Shutdown::Both);
+            let index = (token & INDEX_MASK) - 1;
+            let slot = &mut self.slots[index];
+            slot.socket = None;
+            slot.generation = (slot.generation + 1) % INDEX_MASK;
+            self.free.push(index);
         }
-        self.sockets.remove(&token);
     }
     pub fn get_mut(&mut self, token: usize) -> Option<&mut Socket> {
-        self.sockets.get_mut(&token)
+        let index = (token & INDEX_MASK).checked_sub(1)?;
+        let slot = self.slots.get_mut(index)?;
+        if slot.generation != token >> INDEX_BITS {
+            return None
+        }
+        slot.socket.as_mut()
+    }
+    //send the pending output, waiting for writable events while the kernel buffer is full,
+    //and close the connection once it is done.
+    //returns true if the connection stopped answering requests because of its unsent responses and can continue
+    pub fn flush(&mut self, poll: &mio::Poll, token: usize) -> bool {
+        let socket = match self.get_mut(token) {
+            Some(socket) => socket,
+            None => return false
+        };
+        let full = socket.is_full();
+        let (close, done) = match socket.flush() {
+            Ok(done) => if done == socket.writable {
+                let interest = if done {Interest::READABLE} else {Interest::READABLE | Interest::WRITABLE};
+                socket.writable = !done;
+                (poll.registry().reregister(&mut socket.stream, Token(token), interest).is_err() || (done && socket.can_close()), done)
+            } else {
+                (done && socket.can_close(), done)
+            },
+            Err(_) => (true, false)
+        };
+        if close {
+            self.remove(poll, token);
+        }
+        full && done && !close
+    }
+    pub fn is_empty(&self) -> bool {
+        self.free.len() == self.slots.len()
+    }
+    //close every connection once its pending output is sent
+    pub fn close_all(&mut self, poll: &mio::Poll) {
+        let tokens: Vec<usize> = self.slots.iter()
+            .enumerate()
+            .filter(|(_, slot)| slot.socket.is_some())
+            .map(|(index, slot)| to_token(index, slot.generation))
+            .collect();
+        for token in tokens {
+            if let Some(socket) = self.get_mut(token) {
+                socket.closing = true;
+            }
+            self.flush(poll, token);
+        }
+    }
+    //time until the next connection becomes idle, used as poll timeout
+    pub fn next_timeout(&self) -> Option<Duration> {
+        self.deadlines.peek().map(|Reverse((deadline, _))| deadline.saturating_duration_since(Instant::now()))
+    }
+    //close connections without activity for longer than the idle timeout,
+    //connections waiting for an async route are checked again later
+    pub fn remove_idle(&mut self, poll: &mio::Poll) {
+        let now = Instant::now();
+        let idle_timeout = self.settings.idle_timeout;
+        while let Some(&Reverse((deadline, token))) = self.deadlines.peek() {
+            if deadline > now {
+                break
+            }
+            self.deadlines.pop();
+            let deadline = match self.get_mut(token) {
+                Some(socket) if socket.waiting => now + idle_timeout,
+                Some(socket) => socket.last_activity + idle_timeout,
+                None => continue
+            };
+            if deadline > now {
+                self.deadlines.push(Reverse((deadline, token)));
+            } else {
+                self.remove(poll, token);
+            }
+        }
+    }
+}
+
+fn to_token(index: usize, generation: usize) -> usize {
+    (generation << INDEX_BITS) | (index + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //sockets with one connection from the returned client
+    fn connect(sockets: &mut Sockets, poll: &mio::Poll) -> std::net::TcpStream {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, address) = listener.accept().unwrap();
+        stream.set_nonblocking(true).unwrap();
+        sockets.insert(poll, TcpStream::from_std(stream), address).unwrap();
+        client
+    }
+
+    #[test]
+    fn tokens_of_closed_connections_are_stale() {
+        assert_eq!(to_token(0, 0), 1);
+        assert_eq!(to_token(4, 3), (3 << INDEX_BITS) | 5);
+        assert_eq!(to_token(INDEX_MASK - 1, INDEX_MASK - 1) & INDEX_MASK, INDEX_MASK);
+        assert_ne!(to_token(INDEX_MASK - 1, INDEX_MASK - 1), crate::WAKER_EVENT_TOKEN.0);
+
+        let poll = mio::Poll::new().unwrap();
+        let mut sockets = Sockets::new(Default::default(), None);
+        let _client = connect(&mut sockets, &poll);
+        let first = to_token(0, 0);
+        assert!(sockets.get_mut(first).is_some());
+        assert!(sockets.get_mut(0).is_none());
+        assert!(sockets.get_mut(to_token(1, 0)).is_none());
+        sockets.remove(&poll, first);
+        assert!(sockets.get_mut(first).is_none());
+        assert!(sockets.is_empty());
+        //the slot is reused with the next generation
+        let _client = connect(&mut sockets, &poll);
+        assert!(sockets.get_mut(first).is_none());
+        assert!(sockets.get_mut(to_token(0, 1)).is_some());
+        assert_eq!(sockets.slots.len(), 1);
+    }
+
+    #[test]
+    fn pipelined_responses_keep_the_request_order() {
+        let poll = mio::Poll::new().unwrap();
+        let mut sockets = Sockets::new(Default::default(), None);
+        let mut client = connect(&mut sockets, &poll);
+        let socket = sockets.get_mut(to_token(0, 0)).unwrap();
+        socket.buffer.extend(b"GET /a HTTP/1.1\r\n\r\nHEAD /b HTTP/1.1\r\n\r\nGET /c HTTP/1.1\r\nConnection: close\r\n\r\nGET /d");
+        let mut paths = vec![];
+        while let Some(request) = socket.next_request().unwrap() {
+            socket.start_request(&request);
+            socket.send(Response::ok().body(request.path()));
+            //a second response replaces the first one
+            socket.send(Response::ok().body(request.path().to_ascii_uppercase()));
+            let response = socket.response.take().unwrap();
+            socket.write_response(response);
+            socket.finish_request();
+            paths.push(request.path().to_vec());
+        }
+        assert_eq!(paths, [b"a", b"b", b"c"]);
+        assert!(socket.closing);
+        assert_eq!(socket.buffer, b"GET /d");
+        assert!(socket.flush().unwrap());
+        drop(sockets);
+        let mut received = String::new();
+        client.read_to_string(&mut received).unwrap();
+        assert_eq!(received, "HTTP/1.1 200 OK\r\nConnection: keep-alive\r\nContent-Length: 1\r\n\r\nA\
+            HTTP/1.1 200 OK\r\nConnection: keep-alive\r\nContent-Length: 1\r\n\r\n\
+            HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Length: 1\r\n\r\nC");
+    }
+
+    #[test]
+    fn pauses_on_unsent_responses() {
+        let poll = mio::Poll::new().unwrap();
+        let mut sockets = Sockets::new(crate::SocketSettings {max_pipeline_depth: 2, ..Default::default()}, None);
+        let _client = connect(&mut sockets, &poll);
+        let socket = sockets.get_mut(to_token(0, 0)).unwrap();
+        for _ in 0..2 {
+            assert!(!socket.is_paused());
+            socket.write_response(Response::ok());
+        }
+        assert!(socket.is_full() && socket.is_paused());
+        //resumes once the output is sent
+        assert!(sockets.flush(&poll, to_token(0, 0)));
+        assert!(!sockets.get_mut(to_token(0, 0)).unwrap().is_paused());
+        assert!(!sockets.flush(&poll, to_token(0, 0)));
+    }
+
+    #[test]
+    fn flushes_what_the_kernel_accepts() {
+        let poll = mio::Poll::new().unwrap();
+        let mut sockets = Sockets::new(Default::default(), None);
+        let mut client = connect(&mut sockets, &poll);
+        let socket = sockets.get_mut(to_token(0, 0)).unwrap();
+        let body = vec![7u8; 16 * 1024 * 1024];
+        socket.write_response(Response::ok().body(body.clone()));
+        socket.write_response(Response::ok().stream(&b"streamed"[..], None));
+        //more than the kernel buffer, the rest waits for a writable event
+        assert!(!socket.flush().unwrap());
+        let reader = std::thread::spawn(move || {
+            let mut received = vec![];
+            client.read_to_end(&mut received).unwrap();
+            received
+        });
+        while !socket.flush().unwrap() {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        drop(sockets);
+        let received = reader.join().unwrap();
+        let head = format!("HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Length: {}\r\n\r\n", body.len());
+        assert!(received.starts_with(head.as_bytes()));
+        assert_eq!(&received[head.len()..head.len() + body.len()], &body[..]);
+        assert!(received.ends_with(b"Transfer-Encoding: chunked\r\n\r\n8\r\nstreamed\r\n0\r\n\r\n"));
+    }
+
+    #[test]
+    fn allocates_slots_up_to_the_max() {
+        let mut sockets = Sockets::new(Default::default(), None);
+        assert_eq!(sockets.allocate(2).unwrap(), 0);
+        assert_eq!(sockets.allocate(2).unwrap(), 1);
+        assert!(sockets.allocate(2).is_err());
+        sockets.free.push(0);
+        assert_eq!(sockets.allocate(2).unwrap(), 0);
     }
 }
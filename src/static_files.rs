@@ -1,13 +1,23 @@
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryFrom;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 use std::fs;
-use super::util::get_extension;
+use std::io::{self, Read, Seek, SeekFrom, ErrorKind::UnexpectedEof};
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use super::{Body, CacheControlRule, Method, Request, Response, StaticFilesSettings};
+use super::util::{format_http_date, get_extension, parse_http_date, percent_decode};
 
 pub enum FileType {
     Html, Text, Css, Js, Pdf, Json, Zip, Gif, Jpeg, Png, Svg, Xml, Mpeg, Mp4, Ico, Ttf, Otf, Unknown
 }
 impl FileType {
     pub fn get(v: &[u8]) -> Self {
-        let ext = get_extension(v);
+        Self::get_by_extension(get_extension(v))
+    }
+    pub fn get_by_extension(ext: &[u8]) -> Self {
         match ext {
             [b'h',b't',b'm',b'l'] => Self::Html,
             [b't',b'x',b't'] => Self::Text,
@@ -54,35 +64,224 @@ impl FileType {
     }
 }
 
-pub type File = (&'static [u8], Vec<u8>);
+#[derive(Clone)]
+pub struct File {
+    pub content_type: &'static [u8],
+    //the whole file when it is cached, empty for a file read from `path` while it is sent
+    pub content: Vec<u8>,
+    pub path: Option<PathBuf>,
+    pub length: usize,
+    //validators of this version of the file, computed when it is read
+    pub etag: String,
+    pub last_modified: Option<SystemTime>,
+    //value of the first cache control rule matching the file
+    pub cache_control: Option<&'static str>
+}
 pub type Files = HashMap<Vec<u8>, File>;
 
+impl File {
+    //answer a GET or HEAD request: 304 if the client's copy is up to date,
+    //206 or 416 if it asks for byte ranges and 200 otherwise
+    pub fn respond_to(&self, request: &Request) -> Response {
+        if self.is_fresh(request) {
+            return self.not_modified()
+        }
+        if request.method != Method::Get || !self.matches_if_range(request) {
+            return self.response()
+        }
+        //an invalid "Range" header is ignored
+        match request.header("Range").and_then(|range| parse_ranges(range, self.length)) {
+            Some(ranges) => self.partial(&ranges),
+            None => self.response()
+        }
+    }
+    //200 response with the file and its validators
+    pub fn response(&self) -> Response {
+        let response = self.validators(Response::ok())
+            .header("Accept-Ranges", "bytes")
+            .header("Content-Type", self.content_type);
+        match self.body() {
+            Ok(body) => Response {body, ..response},
+            Err(_) => Response::new(404)
+        }
+    }
+    //the whole file, streamed if it isn't cached
+    pub fn body(&self) -> io::Result<Body> {
+        if self.length == 0 {
+            return Ok(Body::Bytes(vec![]))
+        }
+        self.range(0, self.length - 1)
+    }
+    //206 response with the inclusive byte ranges, in a multipart body if there are more than one,
+    //416 without ranges
+    pub fn partial(&self, ranges: &[(usize, usize)]) -> Response {
+        let length = self.length;
+        let response = self.validators(Response::new(206)).header("Accept-Ranges", "bytes");
+        let (response, body) = match ranges {
+            [] => return Response::new(416).header("Content-Range", format!("bytes */{}", length)),
+            [(first, last)] => (response
+                .header("Content-Type", self.content_type)
+                .header("Content-Range", format!("bytes {}-{}/{}", first, last, length)),
+                self.range(*first, *last)),
+            ranges => {
+                let boundary = self.boundary();
+                let mut parts = Parts {file: None, parts: VecDeque::new()};
+                let mut head = vec![];
+                for (first, last) in ranges {
+                    head.extend(format!("--{}\r\nContent-Type: ", boundary).as_bytes());
+                    head.extend(self.content_type);
+                    head.extend(format!("\r\nContent-Range: bytes {}-{}/{}\r\n\r\n", first, last, length).as_bytes());
+                    parts.parts.push_back((head, *first as u64, (last - first + 1) as u64));
+                    head = b"\r\n".to_vec();
+                }
+                head.extend(format!("--{}--\r\n", boundary).as_bytes());
+                parts.parts.push_back((head, 0, 0));
+                let body = match &self.path {
+                    Some(path) => fs::File::open(path).map(|file| {
+                        let length = parts.length();
+                        parts.file = Some(file);
+                        Body::Stream(Box::new(parts), Some(length))
+                    }),
+                    None => {
+                        let mut body = vec![];
+                        for (head, first, length) in parts.parts {
+                            body.extend(head);
+                            body.extend(&self.content[first as usize..(first + length) as usize]);
+                        }
+                        Ok(Body::Bytes(body))
+                    }
+                };
+                (response.header("Content-Type", format!("multipart/byteranges; boundary={}", boundary)), body)
+            }
+        };
+        match body {
+            Ok(body) => Response {body, ..response},
+            //the file was removed since it was found
+            Err(_) => Response::new(404)
+        }
+    }
+    //inclusive byte range of the file, read from the disk without cache
+    fn range(&self, first: usize, last: usize) -> io::Result<Body> {
+        match &self.path {
+            Some(path) => {
+                let mut file = fs::File::open(path)?;
+                file.seek(SeekFrom::Start(first as u64))?;
+                let length = (last - first + 1) as u64;
+                Ok(Body::Stream(Box::new(file.take(length)), Some(length)))
+            },
+            None => Ok(Body::Bytes(self.content[first..=last].to_vec()))
+        }
+    }
+    //304 response for a client that already has this version of the file
+    pub fn not_modified(&self) -> Response {
+        self.validators(Response::new(304))
+    }
+    //the client's copy is up to date, "If-Modified-Since" is only checked without "If-None-Match"
+    pub fn is_fresh(&self, request: &Request) -> bool {
+        if request.headers.contains("If-None-Match") {
+            //weak comparison, a "W/" prefix is ignored
+            let etag = self.etag.trim_start_matches("W/").as_bytes();
+            return request.headers.get_all("If-None-Match")
+                .flat_map(|v| v.split(|c| *c == b','))
+                .map(|v| v.trim_ascii())
+                .any(|v| v == b"*" || v.strip_prefix(b"W/").unwrap_or(v) == etag)
+        }
+        match (self.last_modified, request.header("If-Modified-Since").and_then(parse_http_date)) {
+            //the header only has whole seconds
+            (Some(last_modified), Some(since)) => last_modified.duration_since(since).map_or(true, |d| d.as_secs() == 0),
+            _ => false
+        }
+    }
+    //"If-Range" only lets the ranges apply to the version of the file the client already has
+    fn matches_if_range(&self, request: &Request) -> bool {
+        match request.header("If-Range") {
+            None => true,
+            //strong comparison, weak etags never match
+            Some(v) if v.starts_with(b"\"") => v == self.etag.as_bytes(),
+            Some(v) => match (parse_http_date(v), self.last_modified) {
+                (Some(date), Some(last_modified)) => seconds(date) == seconds(last_modified),
+                _ => false
+            }
+        }
+    }
+    //separator of the parts of a multipart body, unlikely to be in the content
+    fn boundary(&self) -> String {
+        let mut hasher = DefaultHasher::new();
+        self.etag.hash(&mut hasher);
+        SystemTime::now().hash(&mut hasher);
+        format!("rust_net_{:016x}", hasher.finish())
+    }
+    //headers sent with both the file and 304 responses
+    fn validators(&self, response: Response) -> Response {
+        let mut response = response.header("ETag", &self.etag);
+        if let Some(cache_control) = self.cache_control {
+            response = response.header("Cache-Control", cache_control);
+        }
+        match self.last_modified {
+            Some(last_modified) => response.header("Last-Modified", format_http_date(last_modified)),
+            None => response
+        }
+    }
+}
+
 pub struct StaticFiles {
     pub root_path: &'static str,
-    pub cache: Option<Files>
+    pub cache: Option<Files>,
+    pub not_found_page: Option<&'static str>,
+    pub cache_control: Vec<CacheControlRule>
 }
 
-impl StaticFiles {
-    pub fn new(root_path: &'static str, use_cache: bool) -> StaticFiles {
-        if use_cache {
+impl From<StaticFilesSettings> for StaticFiles {
+    fn from(settings: StaticFilesSettings) -> Self {
+        let mut static_files = StaticFiles {
+            root_path: settings.root_path,
+            cache: None,
+            not_found_page: settings.not_found_page,
+            cache_control: settings.cache_control
+        };
+        if settings.enable_cache {
             let mut cache: Files = HashMap::new();
-            let root_folder = match fs::read_dir(root_path) {
+            let root_folder = match fs::read_dir(static_files.root_path) {
                 Ok(v) => v,
-                Err(e) => panic!("Static files folder not found, path: \"{}\", error: {}", root_path, e)
+                Err(e) => panic!("Static files folder not found, path: \"{}\", error: {}", static_files.root_path, e)
             };
             for path in root_folder {
-                for_eatch_path(path.unwrap().path(), root_path, &mut cache);
+                for_eatch_path(path.unwrap().path(), static_files.root_path, &mut cache);
             }
-            StaticFiles {
-                root_path,
-                cache: Some(cache)
-            }
-        }else {
-            StaticFiles {
-                root_path,
-                cache: None
+            for (path, file) in cache.iter_mut() {
+                file.cache_control = static_files.cache_control(path);
             }
+            static_files.cache = Some(cache);
         }
+        static_files
+    }
+}
+
+impl StaticFiles {
+    pub fn new(root_path: &'static str, use_cache: bool) -> StaticFiles {
+        Self::from(StaticFilesSettings {
+            root_path,
+            enable_cache: use_cache,
+            ..Default::default()
+        })
+    }
+    //file of an already percent-decoded path, from the cache or, without cache, a file of the root folder
+    //that is read while it is sent
+    pub fn get(&self, path: &[u8]) -> Option<Cow<'_, File>> {
+        let path = normalize(path)?;
+        match self.cache {
+            Some(ref cache) => cache.get(path.as_bytes()).map(Cow::Borrowed),
+            None => open_relative(self.root_path, Path::new(&path)).map(|file| Cow::Owned(File {
+                cache_control: self.cache_control(path.as_bytes()),
+                ..file
+            }))
+        }
+    }
+    //value of the first rule matching the path relative to the root folder
+    pub fn cache_control(&self, path: &[u8]) -> Option<&'static str> {
+        self.cache_control.iter()
+            .find(|rule| rule.matches(path))
+            .map(|rule| rule.value)
     }
 }
 
@@ -93,19 +292,196 @@ fn for_eatch_path(path: std::path::PathBuf, root_path: &'static str, cache: &mut
         }
     }else {
         let file_name = path.strip_prefix(root_path).unwrap().to_str().unwrap().replace('\\', "/");
-        if let Some(file) = read_file(root_path, file_name.as_bytes()) {
+        if let Some(file) = read_relative(root_path, Path::new(&file_name)) {
             cache.insert(Vec::from(file_name.as_bytes()), file);
         }
     }
 }
 
+//read a file of the root folder, None if it doesn't exist or if the path leaves the folder
 pub fn read_file(root_path: &'static str, path: &[u8]) -> Option<File> {
-    let file_type = FileType::get(path).to_bytes();
-    let path_str = String::from_utf8_lossy(path).to_string().replace("%20", " ");
-    let full_path = format!("{}/{}", root_path, path_str);
+    read_relative(root_path, &safe_path(path)?)
+}
+
+fn read_relative(root_path: &str, relative: &Path) -> Option<File> {
+    let full_path = resolve(root_path, relative)?;
+    let last_modified = fs::metadata(&full_path).and_then(|metadata| metadata.modified()).ok();
     match fs::read(full_path) {
-        Ok(content) => 
-            Some((file_type, content)),
+        Ok(content) =>
+            Some(File {
+                content_type: FileType::get(relative.to_str()?.as_bytes()).to_bytes(),
+                etag: etag(content.len(), last_modified, || Ok(&content[..])),
+                length: content.len(),
+                content,
+                path: None,
+                last_modified,
+                cache_control: None
+            }),
         Err(_) => None
     }
 }
+
+//file of the root folder without its content, it is read when it is sent
+fn open_relative(root_path: &str, relative: &Path) -> Option<File> {
+    let full_path = resolve(root_path, relative)?;
+    let metadata = fs::metadata(&full_path).ok().filter(|metadata| metadata.is_file())?;
+    let length = usize::try_from(metadata.len()).ok()?;
+    let last_modified = metadata.modified().ok();
+    Some(File {
+        content_type: FileType::get(relative.to_str()?.as_bytes()).to_bytes(),
+        etag: etag(length, last_modified, || fs::File::open(&full_path)),
+        content: vec![],
+        length,
+        path: Some(full_path),
+        last_modified,
+        cache_control: None
+    })
+}
+
+//full path of a file of the root folder, None if it doesn't exist or is outside of the folder
+fn resolve(root_path: &str, relative: &Path) -> Option<PathBuf> {
+    //symbolic links can still point outside of the folder
+    let root = fs::canonicalize(root_path).ok()?;
+    let full_path = fs::canonicalize(root.join(relative)).ok()?;
+    if !full_path.starts_with(&root) {
+        return None
+    }
+    Some(full_path)
+}
+
+//multipart body of a file streamed from the disk, the parts are the headers before a range,
+//its first byte and its length
+struct Parts {
+    file: Option<fs::File>,
+    parts: VecDeque<(Vec<u8>, u64, u64)>
+}
+
+impl Parts {
+    fn length(&self) -> u64 {
+        self.parts.iter().map(|(head, _, length)| head.len() as u64 + length).sum()
+    }
+}
+
+impl Read for Parts {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while let Some((head, first, length)) = self.parts.front_mut() {
+            if !head.is_empty() {
+                let len = head.len().min(buf.len());
+                buf[..len].copy_from_slice(&head[..len]);
+                head.drain(..len);
+                return Ok(len)
+            }
+            if *length > 0 {
+                let file = match &mut self.file {
+                    Some(file) => file,
+                    None => return Err(UnexpectedEof.into())
+                };
+                file.seek(SeekFrom::Start(*first))?;
+                let max = (*length).min(buf.len() as u64) as usize;
+                let len = file.read(&mut buf[..max])?;
+                if len == 0 {
+                    return Err(UnexpectedEof.into())
+                }
+                *first += len as u64;
+                *length -= len as u64;
+                return Ok(len)
+            }
+            self.parts.pop_front();
+        }
+        Ok(0)
+    }
+}
+
+fn seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+//ranges most clients send at once, more are answered with the whole file
+const MAX_RANGES: usize = 32;
+
+//inclusive byte ranges of a "Range" header, sorted with the overlapping ones merged.
+//None if the header is invalid and has to be ignored, an empty list if no range is satisfiable
+pub fn parse_ranges(v: &[u8], length: usize) -> Option<Vec<(usize, usize)>> {
+    let (unit, specs) = std::str::from_utf8(v).ok()?.split_once('=')?;
+    if !unit.trim().eq_ignore_ascii_case("bytes") {
+        return None
+    }
+    let number = |v: &str| if !v.is_empty() && v.bytes().all(|c| c.is_ascii_digit()) {v.parse::<usize>().ok()} else {None};
+    let mut ranges = vec![];
+    let mut specs_count = 0;
+    for spec in specs.split(',').map(|spec| spec.trim()).filter(|spec| !spec.is_empty()) {
+        specs_count += 1;
+        let (first, last) = spec.split_once('-')?;
+        if first.is_empty() {
+            //the last bytes of the file
+            let suffix = number(last)?;
+            if suffix > 0 && length > 0 {
+                ranges.push((length.saturating_sub(suffix), length - 1));
+            }
+        } else {
+            let first = number(first)?;
+            let last = if last.is_empty() {usize::MAX} else {number(last)?};
+            if last < first {
+                return None
+            }
+            if first < length {
+                ranges.push((first, last.min(length - 1)));
+            }
+        }
+    }
+    if specs_count == 0 || specs_count > MAX_RANGES {
+        return None
+    }
+    ranges.sort_unstable();
+    let mut merged: Vec<(usize, usize)> = vec![];
+    for (first, last) in ranges {
+        match merged.last_mut() {
+            Some(previous) if first <= previous.1 + 1 => previous.1 = previous.1.max(last),
+            _ => merged.push((first, last))
+        }
+    }
+    Some(merged)
+}
+
+//length and modification time of the file, or a hash of the content if the time isn't available
+fn etag<R: Read>(length: usize, last_modified: Option<SystemTime>, content: impl FnOnce() -> io::Result<R>) -> String {
+    if let Some(time) = last_modified.and_then(|time| time.duration_since(UNIX_EPOCH).ok()) {
+        return format!("\"{:x}-{:x}\"", length, time.as_nanos())
+    }
+    let mut hasher = DefaultHasher::new();
+    if let Ok(mut content) = content() {
+        let mut buff = [0u8; 8192];
+        while let Ok(len @ 1..) = content.read(&mut buff) {
+            hasher.write(&buff[..len]);
+        }
+    }
+    format!("\"{:x}-{:x}\"", length, hasher.finish())
+}
+
+//percent-decode and normalize a request path into a path relative to the root folder,
+//None if it has ".." or NUL bytes or a segment that is absolute on its own, like "C:" on windows
+pub fn safe_path(path: &[u8]) -> Option<PathBuf> {
+    normalize(&percent_decode(path)?).map(PathBuf::from)
+}
+
+//"a/b" form of an already decoded path, the way files are cached
+fn normalize(path: &[u8]) -> Option<String> {
+    let path = std::str::from_utf8(path).ok()?;
+    if path.contains('\0') {
+        return None
+    }
+    let mut segments = vec![];
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => continue,
+            ".." => return None,
+            //backslashes are separators on windows
+            segment if segment.contains('\\') => return None,
+            segment => match Path::new(segment).components().next() {
+                Some(Component::Normal(_)) => segments.push(segment),
+                _ => return None
+            }
+        }
+    }
+    Some(segments.join("/"))
+}
@@ -1,8 +1,6 @@
-pub const U8_G: u8 = 'G' as u8;
-pub const U8_P: u8 = 'P' as u8;
-pub const U8_O: u8 = 'O' as u8;
-pub const U8_SPACE: u8 = ' ' as u8;
-pub const INDEX: &'static[u8] = &['i' as u8,'n' as u8,'d' as u8,'e' as u8,'x' as u8,'.' as u8,'h' as u8,'t' as u8,'m' as u8,'l' as u8];
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const INDEX: &[u8] = b"index.html";
 
 #[macro_export]
 macro_rules! vec_u8_to_str {
@@ -14,21 +12,135 @@ macro_rules! vec_u8_to_str {
     };
 }
 
-pub fn read_data_until_space(data: &Vec<u8>, start: usize) -> &[u8] {
-    let mut end = start;
-    let max = data.len();
-    while data[end] != U8_SPACE && end < max  {
-        end += 1;
-    }
-    &data[start..end]
-}
-
 pub fn get_extension(v: &[u8]) -> &[u8] {
+    if v.len() < 2 {return &[]}
     let mut i = v.len() - 1;
-    if i < 1 || v[i] == b'.' {return &[]}
+    if v[i] == b'.' {return &[]}
     while i > 0 && v[i] != b'.' {
         i -= 1;
     }
     i += 1;
     &v[i..]
 }
+
+//decode "%XX" sequences, returns None if a sequence is not valid hex
+pub fn percent_decode(v: &[u8]) -> Option<Vec<u8>> {
+    let mut res = Vec::with_capacity(v.len());
+    let mut i = 0;
+    while i < v.len() {
+        if v[i] == b'%' {
+            let hex = v.get(i+1..i+3)?;
+            res.push((hex_value(hex[0])? << 4) | hex_value(hex[1])?);
+            i += 3;
+        }else {
+            res.push(v[i]);
+            i += 1;
+        }
+    }
+    Some(res)
+}
+
+fn hex_value(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None
+    }
+}
+
+pub const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+pub const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+
+//calendar date and time in UTC, month and day start at 1 and weekday 0 is sunday
+pub struct DateTime {
+    pub year: u64,
+    pub month: u64,
+    pub day: u64,
+    pub hour: u64,
+    pub minute: u64,
+    pub second: u64,
+    pub weekday: u64
+}
+
+impl From<SystemTime> for DateTime {
+    fn from(time: SystemTime) -> Self {
+        let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        let days = secs / 86400;
+        //days since 0000-03-01, so leap days end the year
+        let days_from_march = days + 719_468;
+        let era = days_from_march / 146_097;
+        let day_of_era = days_from_march % 146_097;
+        let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_from_march = (5 * day_of_year + 2) / 153;
+        let month = if month_from_march < 10 {month_from_march + 3} else {month_from_march - 9};
+        Self {
+            year: era * 400 + year_of_era + if month <= 2 {1} else {0},
+            month,
+            day: day_of_year - (153 * month_from_march + 2) / 5 + 1,
+            hour: secs % 86400 / 3600,
+            minute: secs % 3600 / 60,
+            second: secs % 60,
+            //1970-01-01 was a thursday
+            weekday: (days + 4) % 7
+        }
+    }
+}
+
+//IMF-fixdate used by HTTP headers, like "Sun, 06 Nov 1994 08:49:37 GMT"
+pub fn format_http_date(time: SystemTime) -> String {
+    let time = DateTime::from(time);
+    format!("{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        WEEKDAYS[time.weekday as usize], time.day, MONTHS[time.month as usize - 1], time.year,
+        time.hour, time.minute, time.second)
+}
+
+//parse an IMF-fixdate, the obsolete formats aren't sent by current clients
+pub fn parse_http_date(v: &[u8]) -> Option<SystemTime> {
+    let v = std::str::from_utf8(v).ok()?;
+    let mut parts = v.split_ascii_whitespace();
+    let _weekday = parts.next()?;
+    let day: u64 = parts.next()?.parse().ok()?;
+    let month = parts.next()?;
+    let month = MONTHS.iter().position(|v| *v == month)? as u64 + 1;
+    let year: u64 = parts.next()?.parse().ok()?;
+    let mut time = parts.next()?.split(':').map(|v| v.parse::<u64>().ok());
+    let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+    //four digit years, bigger ones would overflow the conversion
+    if parts.next()? != "GMT" || !(1..=31).contains(&day) || !(1970..=9999).contains(&year) || hour > 23 || minute > 59 || second > 60 {
+        return None
+    }
+    //inverse of the conversion in `DateTime::from`
+    let year_from_march = if month <= 2 {year - 1} else {year};
+    let era = year_from_march / 400;
+    let year_of_era = year_from_march % 400;
+    let month_from_march = (month + 9) % 12;
+    let day_of_year = (153 * month_from_march + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era.checked_mul(146_097)?.checked_add(day_of_era)?.checked_sub(719_468)?;
+    let secs = days.checked_mul(86400)?.checked_add(hour * 3600 + minute * 60 + second)?;
+    UNIX_EPOCH.checked_add(Duration::from_secs(secs))
+}
+
+//match a path against a glob where '*' and '?' don't match '/' and "**" matches any number of folders
+pub fn glob_match(pattern: &[u8], path: &[u8]) -> bool {
+    match pattern {
+        [] => path.is_empty(),
+        [b'*', b'*', rest @ ..] => {
+            //"**/" also matches no folder at all
+            if let Some(rest) = rest.strip_prefix(b"/") {
+                if glob_match(rest, path) {
+                    return true
+                }
+            }
+            (0..=path.len()).any(|i| glob_match(rest, &path[i..]))
+        },
+        [b'*', rest @ ..] => {
+            let segment_end = path.iter().position(|c| *c == b'/').unwrap_or(path.len());
+            (0..=segment_end).any(|i| glob_match(rest, &path[i..]))
+        },
+        [b'?', rest @ ..] => matches!(path.first(), Some(c) if *c != b'/') && glob_match(rest, &path[1..]),
+        [c, rest @ ..] => path.first() == Some(c) && glob_match(rest, &path[1..])
+    }
+}
@@ -0,0 +1,335 @@
+use mio::{Events, Interest, Poll, Waker};
+use mio::net::{TcpListener, TcpStream};
+use std::io::ErrorKind::{WouldBlock, Interrupted, ConnectionAborted, ConnectionReset};
+use std::net::SocketAddr;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, mpsc::Receiver};
+use std::time::{Duration, Instant};
+use super::*;
+use super::executor::Executor;
+
+//event loop serving connections, either accepted by itself from the listener or
+//received from the accept thread when the server runs multiple workers
+pub(crate) struct Worker<T> {
+    poll: Poll,
+    listener: Option<TcpListener>,
+    incoming: Option<Receiver<(TcpStream, SocketAddr)>>,
+    sockets: Sockets,
+    static_files: Arc<Option<StaticFiles>>,
+    routes: Routes<T>,
+    middlewares: Middlewares<T>,
+    context: T,
+    shutdown: ShutdownHandle,
+    shutdown_timeout: Duration,
+    //accepting stopped on an error, retried after ACCEPT_RETRY_DELAY
+    accept_failed: bool
+}
+
+impl<T: 'static> Worker<T> {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        mut listener: Option<TcpListener>,
+        incoming: Option<Receiver<(TcpStream, SocketAddr)>>,
+        sockets: Sockets,
+        static_files: Arc<Option<StaticFiles>>,
+        routes: Routes<T>,
+        middlewares: Middlewares<T>,
+        context: T,
+        shutdown: ShutdownHandle,
+        shutdown_timeout: Duration
+    ) -> Result<Self, Error> {
+        let poll = Poll::new().map_err(Error::Poll)?;
+        if let Some(listener) = &mut listener {
+            poll.registry().register(listener, LISTENER_EVENT_TOKEN, Interest::READABLE).map_err(Error::Register)?;
+        }
+        //wakes the worker on shutdown, when the accept thread sends a connection and when a future is woken
+        shutdown.set_waker(Waker::new(poll.registry(), WAKER_EVENT_TOKEN).map_err(Error::Register)?);
+        Ok(Self {
+            poll,
+            listener,
+            incoming,
+            sockets,
+            static_files,
+            routes,
+            middlewares,
+            context,
+            shutdown,
+            shutdown_timeout,
+            accept_failed: false
+        })
+    }
+    pub fn run(mut self) -> Result<(), Error> {
+        //created here as futures stay on the worker's thread
+        let mut executor = Executor::new(self.shutdown.clone());
+        let mut deadline = None;
+        let mut events = Events::with_capacity(1024);
+        loop {
+            //stop accepting and wait for the pending responses
+            if deadline.is_none() && self.shutdown.is_shutdown() {
+                if let Some(mut listener) = self.listener.take() {
+                    let _ = self.poll.registry().deregister(&mut listener);
+                }
+                self.incoming = None;
+                self.accept_failed = false;
+                self.sockets.close_all(&self.poll);
+                deadline = Some(Instant::now() + self.shutdown_timeout);
+            }
+            let mut timeout = self.sockets.next_timeout();
+            if self.accept_failed {
+                timeout = Some(timeout.map_or(ACCEPT_RETRY_DELAY, |timeout| timeout.min(ACCEPT_RETRY_DELAY)));
+            }
+            if let Some(deadline) = deadline {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if self.sockets.is_empty() || remaining == Duration::from_secs(0) {
+                    return Ok(())
+                }
+                timeout = Some(timeout.map_or(remaining, |timeout| timeout.min(remaining)));
+            }
+            if let Err(e) = self.poll.poll(&mut events, timeout) {
+                if e.kind() == Interrupted {
+                    continue
+                }
+                return Err(Error::Poll(e))
+            }
+            for event in events.iter() {
+                self.new_event(event, &mut executor);
+            }
+            if self.accept_failed {
+                self.accept();
+            }
+            self.run_tasks(&mut executor);
+            self.sockets.remove_idle(&self.poll);
+        }
+    }
+    fn new_event(&mut self, event: &mio::event::Event, executor: &mut Executor) {
+        match event.token() {
+            LISTENER_EVENT_TOKEN => self.accept(),
+            //woken up by a shutdown, handled in the run loop, or by new connections
+            WAKER_EVENT_TOKEN => if let Some(incoming) = &self.incoming {
+                for (stream, address) in incoming.try_iter() {
+                    if let Err(e) = self.sockets.insert(&self.poll, stream, address) {
+                        eprintln!("Couldn't register connection from {}: {}", address, e);
+                    }
+                }
+            },
+            mio::Token(token) => {
+                if let Some(socket) = self.sockets.get_mut(token) {
+                    if event.is_readable() {
+                        socket.readable = true;
+                        self.serve(token, executor);
+                    }
+                }
+                self.flush(token, executor);
+            }
+        }
+    }
+    //answer the buffered requests of a connection in the order they arrived,
+    //stopping at a request answered by an async route until its future completes
+    //and while too many responses weren't sent yet
+    fn dispatch(&mut self, token: usize, executor: &mut Executor) {
+        let (static_files, routes, middlewares, context) = (&self.static_files, &mut self.routes, &mut self.middlewares, &mut self.context);
+        if let Some(socket) = self.sockets.get_mut(token) {
+            while !socket.closing && !socket.is_paused() {
+                match socket.next_request() {
+                    Ok(Some(request)) => {
+                        socket.start_request(&request);
+                        //a panicking middleware or route gets a 500 instead of taking the worker down
+                        let res = panic::catch_unwind(AssertUnwindSafe(|| Self::handle_request(
+                            request, token, socket, static_files, routes, middlewares, context, executor
+                        )));
+                        if res.is_err() || (!socket.waiting && !socket.responded) {
+                            Self::server_error(socket, routes, context);
+                        }
+                        if !socket.waiting {
+                            Self::respond(socket, middlewares, context);
+                            socket.finish_request();
+                        }
+                    },
+                    //keep the connection until the rest of the request arrives
+                    Ok(None) => break,
+                    Err(e) => {
+                        let response = socket.reject(e);
+                        socket.write_response(routes.error(context, None, response));
+                    }
+                }
+            }
+        }
+    }
+    fn accept(&mut self) {
+        if let Some(listener) = &self.listener {
+            let (poll, sockets) = (&self.poll, &mut self.sockets);
+            self.accept_failed = !accept(listener, |stream, address| if let Err(e) = sockets.insert(poll, stream, address) {
+                eprintln!("Couldn't register connection from {}: {}", address, e);
+            });
+        }
+    }
+    //send the replies of the completed async routes and continue with the next requests
+    fn run_tasks(&mut self, executor: &mut Executor) {
+        for (token, reply) in executor.run() {
+            if let Some(socket) = self.sockets.get_mut(token) {
+                socket.waiting = false;
+                //a future or reply that panicked, or a reply that doesn't send a response, gets a 500
+                let sent = match reply {
+                    Some(reply) => panic::catch_unwind(AssertUnwindSafe(|| reply.send(socket))).is_ok() && socket.responded,
+                    None => false
+                };
+                if !sent {
+                    Self::server_error(socket, &mut self.routes, &mut self.context);
+                }
+                Self::respond(socket, &mut self.middlewares, &mut self.context);
+                socket.finish_request();
+                self.serve(token, executor);
+            }
+            self.flush(token, executor);
+        }
+    }
+    //send the output of a connection, answering its next requests once the client read the previous responses
+    fn flush(&mut self, token: usize, executor: &mut Executor) {
+        while self.sockets.flush(&self.poll, token) {
+            self.serve(token, executor);
+        }
+    }
+    //answer the buffered requests and read the next ones until the connection is paused
+    //or everything it sent was read
+    fn serve(&mut self, token: usize, executor: &mut Executor) {
+        loop {
+            self.dispatch(token, executor);
+            let socket = match self.sockets.get_mut(token) {
+                Some(socket) => socket,
+                None => return
+            };
+            if !socket.readable || socket.closing || socket.is_paused() {
+                return
+            }
+            if !socket.read_stream() {
+                //answer what was received before the connection was closed
+                self.dispatch(token, executor);
+                if let Some(socket) = self.sockets.get_mut(token) {
+                    socket.closing = true;
+                }
+                return
+            }
+        }
+    }
+    //let the middlewares decorate the response of the current request and write it
+    fn respond(socket: &mut Socket, middlewares: &mut Middlewares<T>, context: &mut T) {
+        let request = socket.request.take();
+        if let Some(mut response) = socket.response.take() {
+            if let Some(request) = request {
+                //a panicking middleware gets the response replaced by a 500
+                if panic::catch_unwind(AssertUnwindSafe(|| middlewares.after(context, &request, &mut response, socket.middlewares))).is_err() {
+                    response = Response::new(500);
+                }
+            }
+            socket.write_response(response);
+        }
+    }
+    #[allow(clippy::too_many_arguments)]
+    fn handle_request(
+        mut request: Request,
+        token: usize,
+        socket: &mut Socket,
+        static_files: &Option<StaticFiles>,
+        routes: &mut Routes<T>,
+        middlewares: &mut Middlewares<T>,
+        context: &mut T,
+        executor: &mut Executor
+    ) {
+        if let Some((count, response)) = middlewares.before(context, &mut request) {
+            request.body = vec![];
+            socket.request = Some(request);
+            socket.middlewares = count;
+            return socket.send(response)
+        }
+        if !middlewares.is_empty() {
+            socket.request = Some(request.without_body());
+            socket.middlewares = middlewares.len();
+        }
+        //routes decode the segments of the raw path, so an encoded '/' doesn't separate them
+        let raw_path = request.raw_path().to_vec();
+        let path = raw_path.as_slice();
+        //HEAD requests without their own route are answered like GET requests, without the body
+        let method = if socket.head && !routes.contains(&Method::Head, path) {
+            &Method::Get
+        } else {
+            &request.method
+        };
+        if *method == Method::Get {
+            //static files have priority over get routes, "/" is the index file
+            let file_path = if request.path.is_empty() {util::INDEX} else {&request.path};
+            if let Some(file) = static_files.as_ref().and_then(|static_files| static_files.get(file_path)) {
+                return socket.send(file.respond_to(&request))
+            }
+        }
+        let (route, params) = match routes.get_mut(method, path) {
+            Some(route) => route,
+            None => match routes.fallback {
+                Some(ref mut fallback) => (fallback, Params::default()),
+                None => {
+                    let allowed: Vec<String> = routes.allowed_methods(path).iter().map(|method| method.to_string()).collect();
+                    let response = if allowed.is_empty() {
+                        not_found(static_files)
+                    } else {
+                        Response::new(405).header("Allow", allowed.join(", "))
+                    };
+                    return socket.send(routes.error(context, Some(&request), response))
+                }
+            }
+        };
+        if let Some(request) = &mut socket.request {
+            request.params = params.clone();
+        }
+        request.params = params;
+        match route {
+            Route::Sync(handler) => handler.call(context, socket, request),
+            Route::Async(handler) => {
+                let future = handler.call(context, request);
+                socket.waiting = true;
+                executor.spawn(token, future);
+            }
+        }
+    }
+    //answer a request whose route or middleware panicked, or whose route didn't send a response
+    fn server_error(socket: &mut Socket, routes: &mut Routes<T>, context: &mut T) {
+        socket.waiting = false;
+        socket.send(routes.error(context, None, Response::new(500)));
+    }
+}
+
+//404 response, with the not found page of the static files if there is one
+fn not_found(static_files: &Option<StaticFiles>) -> Response {
+    let page = static_files.as_ref().and_then(|static_files| {
+        static_files.get(static_files.not_found_page?.as_bytes())
+    });
+    match page {
+        Some(page) => match page.body() {
+            Ok(body) => Response {body, ..Response::new(404).header("Content-Type", page.content_type)},
+            Err(_) => Response::new(404)
+        },
+        None => Response::new(404)
+    }
+}
+
+//delay before accepting again after an error like running out of file descriptors
+pub(crate) const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+//accept every pending connection, the listener only gets a new event for new connections.
+//returns false if accepting stopped on an error, the pending connections get no new event
+//so accepting has to be retried
+pub(crate) fn accept(listener: &TcpListener, mut on_accept: impl FnMut(TcpStream, SocketAddr)) -> bool {
+    loop {
+        match listener.accept() {
+            Ok((stream, address)) => on_accept(stream, address),
+            Err(ref e) if e.kind() == WouldBlock => return true,
+            Err(ref e) if e.kind() == Interrupted => continue,
+            //errors of a single connection, like an aborted handshake
+            Err(ref e) if matches!(e.kind(), ConnectionAborted | ConnectionReset) =>
+                eprintln!("Couldn't accept connection: {}", e),
+            //errors like running out of file descriptors, retried after ACCEPT_RETRY_DELAY
+            Err(e) => {
+                eprintln!("Couldn't accept connection: {}", e);
+                return false
+            }
+        }
+    }
+}
@@ -0,0 +1,261 @@
+use rust_net::{read_file, safe_path, CacheControlRule, File, Request, StaticFiles, StaticFilesSettings};
+use rust_net::util::format_http_date;
+use std::io::Read;
+use std::path::PathBuf;
+
+const ROOT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/public");
+
+#[test]
+fn reads_files_inside_the_root() {
+    let file = read_file(ROOT, b"css/main.css").unwrap();
+    assert_eq!(file.content_type, b"text/css");
+    assert_eq!(file.content, std::fs::read(format!("{}/css/main.css", ROOT)).unwrap());
+    assert!(read_file(ROOT, b"/index.html").is_some());
+    assert!(read_file(ROOT, b"./css/./main.css").is_some());
+    assert!(read_file(ROOT, b"css%2Fmain.css").is_some());
+}
+
+#[test]
+fn rejects_parent_segments() {
+    assert!(read_file(ROOT, b"../Cargo.toml").is_none());
+    assert!(read_file(ROOT, b"/../Cargo.toml").is_none());
+    assert!(read_file(ROOT, b"css/../../Cargo.toml").is_none());
+    assert!(read_file(ROOT, b"css/../index.html").is_none());
+}
+
+#[test]
+fn rejects_encoded_parent_segments() {
+    assert!(read_file(ROOT, b"%2e%2e/Cargo.toml").is_none());
+    assert!(read_file(ROOT, b"%2E%2E%2FCargo.toml").is_none());
+    assert!(read_file(ROOT, b"..%2fCargo.toml").is_none());
+    assert!(read_file(ROOT, b"css%2f..%2f..%2fCargo.toml").is_none());
+    //decoded once, "%252e" stays a literal "%2e" file name
+    assert!(read_file(ROOT, b"%252e%252e/Cargo.toml").is_none());
+}
+
+#[test]
+fn rejects_nul_bytes_and_invalid_encodings() {
+    assert!(read_file(ROOT, b"index.html%00.png").is_none());
+    assert!(read_file(ROOT, b"index.html\0").is_none());
+    assert!(read_file(ROOT, b"index%2").is_none());
+    assert!(read_file(ROOT, b"index%zz.html").is_none());
+}
+
+#[test]
+fn rejects_absolute_and_backslash_segments() {
+    assert_eq!(safe_path(b"//etc/passwd"), Some(PathBuf::from("etc/passwd")));
+    assert!(read_file(ROOT, b"//etc/passwd").is_none());
+    assert!(safe_path(b"..\\Cargo.toml").is_none());
+    assert!(safe_path(b"css%5c..%5c..%5cCargo.toml").is_none());
+    assert_eq!(safe_path(b"/a/./b/"), Some(PathBuf::from("a/b")));
+}
+
+#[cfg(unix)]
+#[test]
+fn rejects_symbolic_links_leaving_the_root() {
+    let root = std::env::temp_dir().join(format!("rust_net_static_files_{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(root.join("inside.txt"), b"inside").unwrap();
+    let _ = std::fs::remove_file(root.join("outside.toml"));
+    std::os::unix::fs::symlink(concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml"), root.join("outside.toml")).unwrap();
+    let root_path: &'static str = Box::leak(root.to_str().unwrap().to_string().into_boxed_str());
+    assert_eq!(read_file(root_path, b"inside.txt").unwrap().content, b"inside");
+    assert!(read_file(root_path, b"outside.toml").is_none());
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn decodes_reserved_and_non_ascii_characters() {
+    assert_eq!(safe_path(b"caf%C3%A9.png"), Some(PathBuf::from("café.png")));
+    assert_eq!(safe_path(b"a%2Bb.js"), Some(PathBuf::from("a+b.js")));
+    assert_eq!(safe_path(b"my%20file.txt"), Some(PathBuf::from("my file.txt")));
+    //not valid utf-8
+    assert!(safe_path(b"caf%E9.png").is_none());
+}
+
+fn request(headers: &str) -> Request {
+    let data = format!("GET /index.html HTTP/1.1\r\n{}\r\n", headers);
+    Request::parse(data.as_bytes(), 8192, 8192).unwrap().unwrap().0
+}
+
+#[test]
+fn sends_validators() {
+    let file = read_file(ROOT, b"index.html").unwrap();
+    let response = file.response();
+    assert_eq!(response.headers.get("ETag"), Some(file.etag.as_bytes()));
+    let last_modified = format_http_date(file.last_modified.unwrap());
+    assert_eq!(response.headers.get("Last-Modified"), Some(last_modified.as_bytes()));
+    let response = file.not_modified();
+    assert_eq!(response.status, 304);
+    assert!(!response.has_body());
+}
+
+#[test]
+fn checks_if_none_match() {
+    let file = read_file(ROOT, b"index.html").unwrap();
+    assert!(file.is_fresh(&request(&format!("If-None-Match: {}\r\n", file.etag))));
+    assert!(file.is_fresh(&request(&format!("If-None-Match: \"other\", W/{}\r\n", file.etag))));
+    assert!(file.is_fresh(&request("If-None-Match: *\r\n")));
+    assert!(!file.is_fresh(&request("If-None-Match: \"other\"\r\n")));
+    //If-Modified-Since is ignored when If-None-Match is sent
+    assert!(!file.is_fresh(&request("If-None-Match: \"other\"\r\nIf-Modified-Since: Fri, 31 Dec 9999 23:59:59 GMT\r\n")));
+    assert!(!file.is_fresh(&request("")));
+}
+
+#[test]
+fn checks_if_modified_since() {
+    let file = read_file(ROOT, b"index.html").unwrap();
+    let last_modified = format_http_date(file.last_modified.unwrap());
+    assert!(file.is_fresh(&request(&format!("If-Modified-Since: {}\r\n", last_modified))));
+    assert!(file.is_fresh(&request("If-Modified-Since: Fri, 31 Dec 9999 23:59:59 GMT\r\n")));
+    assert!(!file.is_fresh(&request("If-Modified-Since: Thu, 01 Jan 1970 00:00:00 GMT\r\n")));
+    assert!(!file.is_fresh(&request("If-Modified-Since: yesterday\r\n")));
+}
+
+#[test]
+fn ignores_out_of_range_dates() {
+    use rust_net::util::parse_http_date;
+    assert!(parse_http_date(b"Fri, 31 Dec 9999 23:59:59 GMT").is_some());
+    assert!(parse_http_date(b"Fri, 31 Dec 10000 23:59:59 GMT").is_none());
+    assert!(parse_http_date(b"Fri, 31 Dec 500000000000 23:59:59 GMT").is_none());
+    assert!(parse_http_date(b"Fri, 31 Dec 18446744073709551615 23:59:59 GMT").is_none());
+    let file = read_file(ROOT, b"index.html").unwrap();
+    assert!(!file.is_fresh(&request("If-Modified-Since: Fri, 31 Dec 500000000000 23:59:59 GMT\r\n")));
+    assert_eq!(file.respond_to(&request("Range: bytes=0-9\r\nIf-Range: Fri, 31 Dec 500000000000 23:59:59 GMT\r\n")).status, 200);
+}
+
+#[test]
+fn matches_globs() {
+    use rust_net::util::glob_match;
+    assert!(glob_match(b"index.html", b"index.html"));
+    assert!(glob_match(b"*.css", b"main.css"));
+    assert!(!glob_match(b"*.css", b"css/main.css"));
+    assert!(glob_match(b"css/*.css", b"css/main.css"));
+    assert!(glob_match(b"**/*.css", b"main.css"));
+    assert!(glob_match(b"**/*.css", b"a/b/main.css"));
+    assert!(glob_match(b"assets/**", b"assets/a/b.js"));
+    assert!(!glob_match(b"assets/**", b"other/a.js"));
+    assert!(glob_match(b"app.????????.js", b"app.1a2b3c4d.js"));
+    assert!(!glob_match(b"app.????????.js", b"app.js"));
+}
+
+#[test]
+fn applies_the_first_matching_cache_control_rule() {
+    for enable_cache in [false, true] {
+        let static_files = StaticFiles::from(StaticFilesSettings {
+            root_path: ROOT,
+            enable_cache,
+            cache_control: vec![
+                CacheControlRule::glob("index.html", "no-cache"),
+                CacheControlRule::extension("CSS", "max-age=31536000, immutable"),
+                CacheControlRule::glob("**", "max-age=60")
+            ],
+            ..Default::default()
+        });
+        let file = static_files.get(b"index.html").unwrap();
+        assert_eq!(file.response().headers.get("Cache-Control"), Some(&b"no-cache"[..]));
+        assert_eq!(file.not_modified().headers.get("Cache-Control"), Some(&b"no-cache"[..]));
+        let file = static_files.get(b"css/main.css").unwrap();
+        assert_eq!(file.cache_control, Some("max-age=31536000, immutable"));
+        let file = static_files.get(b"favicon.ico").unwrap();
+        assert_eq!(file.cache_control, Some("max-age=60"));
+    }
+    let file = StaticFiles::new(ROOT, false).get(b"index.html").unwrap().into_owned();
+    assert!(file.response().headers.get("Cache-Control").is_none());
+}
+
+#[test]
+fn parses_ranges() {
+    use rust_net::parse_ranges;
+    assert_eq!(parse_ranges(b"bytes=0-499", 1000), Some(vec![(0, 499)]));
+    assert_eq!(parse_ranges(b"bytes=500-", 1000), Some(vec![(500, 999)]));
+    assert_eq!(parse_ranges(b"bytes=-100", 1000), Some(vec![(900, 999)]));
+    assert_eq!(parse_ranges(b"bytes=-2000", 1000), Some(vec![(0, 999)]));
+    assert_eq!(parse_ranges(b"bytes=900-5000", 1000), Some(vec![(900, 999)]));
+    assert_eq!(parse_ranges(b"Bytes = 0-0, -1", 1000), Some(vec![(0, 0), (999, 999)]));
+    //overlapping and adjacent ranges are merged
+    assert_eq!(parse_ranges(b"bytes=500-600,0-99,100-199,550-700", 1000), Some(vec![(0, 199), (500, 700)]));
+    //unsatisfiable
+    assert_eq!(parse_ranges(b"bytes=1000-", 1000), Some(vec![]));
+    assert_eq!(parse_ranges(b"bytes=-0", 1000), Some(vec![]));
+    //invalid headers are ignored
+    assert_eq!(parse_ranges(b"bytes=5-1", 1000), None);
+    assert_eq!(parse_ranges(b"bytes=a-b", 1000), None);
+    assert_eq!(parse_ranges(b"bytes=+1-2", 1000), None);
+    assert_eq!(parse_ranges(b"items=0-1", 1000), None);
+    assert_eq!(parse_ranges(b"bytes=", 1000), None);
+}
+
+fn body(response: rust_net::Response) -> Vec<u8> {
+    match response.body {
+        rust_net::Body::Bytes(bytes) => bytes,
+        rust_net::Body::Stream(mut reader, length) => {
+            let mut bytes = vec![];
+            reader.read_to_end(&mut bytes).unwrap();
+            assert_eq!(Some(bytes.len() as u64), length);
+            bytes
+        }
+    }
+}
+
+#[test]
+fn streams_files_without_cache() {
+    let content = std::fs::read(format!("{}/index.html", ROOT)).unwrap();
+    let static_files = StaticFiles::new(ROOT, false);
+    let file = static_files.get(b"index.html").unwrap();
+    assert!(file.content.is_empty());
+    assert_eq!(file.length, content.len());
+    let response = file.respond_to(&request(""));
+    assert!(matches!(response.body, rust_net::Body::Stream(..)));
+    assert_eq!(body(response), content);
+    let response = file.respond_to(&request("Range: bytes=5-14\r\n"));
+    assert!(matches!(response.body, rust_net::Body::Stream(..)));
+    assert_eq!(body(response), &content[5..15]);
+    assert!(static_files.get(b"css").is_none());
+}
+
+#[test]
+fn answers_range_requests() {
+    answer_range_requests(read_file(ROOT, b"index.html").unwrap());
+    answer_range_requests(StaticFiles::new(ROOT, false).get(b"index.html").unwrap().into_owned());
+}
+
+fn answer_range_requests(file: File) {
+    let content = std::fs::read(format!("{}/index.html", ROOT)).unwrap();
+    let length = content.len();
+    let response = file.respond_to(&request(""));
+    assert_eq!(response.status, 200);
+    assert_eq!(response.headers.get("Accept-Ranges"), Some(&b"bytes"[..]));
+
+    let response = file.respond_to(&request("Range: bytes=0-9\r\n"));
+    assert_eq!(response.status, 206);
+    assert_eq!(response.headers.get("Content-Range"), Some(format!("bytes 0-9/{}", length).as_bytes()));
+    assert_eq!(body(response), &content[..10]);
+
+    let response = file.respond_to(&request("Range: bytes=0-1,-2\r\n"));
+    assert_eq!(response.status, 206);
+    let content_type = String::from_utf8(response.headers.get("Content-Type").unwrap().to_vec()).unwrap();
+    let boundary = content_type.strip_prefix("multipart/byteranges; boundary=").unwrap().to_string();
+    let mut expected = format!("--{}\r\nContent-Type: text/html\r\nContent-Range: bytes 0-1/{}\r\n\r\n", boundary, length).into_bytes();
+    expected.extend(&content[..2]);
+    expected.extend(format!("\r\n--{}\r\nContent-Type: text/html\r\nContent-Range: bytes {}-{}/{}\r\n\r\n", boundary, length - 2, length - 1, length).as_bytes());
+    expected.extend(&content[length - 2..]);
+    expected.extend(format!("\r\n--{}--\r\n", boundary).as_bytes());
+    assert_eq!(body(response), expected);
+
+    let response = file.respond_to(&request(&format!("Range: bytes={}-\r\n", length)));
+    assert_eq!(response.status, 416);
+    assert_eq!(response.headers.get("Content-Range"), Some(format!("bytes */{}", length).as_bytes()));
+
+    assert_eq!(file.respond_to(&request("Range: bytes=9-0\r\n")).status, 200);
+}
+
+#[test]
+fn checks_if_range() {
+    let file = read_file(ROOT, b"index.html").unwrap();
+    let last_modified = format_http_date(file.last_modified.unwrap());
+    assert_eq!(file.respond_to(&request(&format!("Range: bytes=0-9\r\nIf-Range: {}\r\n", file.etag))).status, 206);
+    assert_eq!(file.respond_to(&request(&format!("Range: bytes=0-9\r\nIf-Range: {}\r\n", last_modified))).status, 206);
+    assert_eq!(file.respond_to(&request("Range: bytes=0-9\r\nIf-Range: \"other\"\r\n")).status, 200);
+    assert_eq!(file.respond_to(&request("Range: bytes=0-9\r\nIf-Range: Thu, 01 Jan 1970 00:00:00 GMT\r\n")).status, 200);
+}